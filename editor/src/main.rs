@@ -1,15 +1,25 @@
-#![allow(clippy::type_complexity)]
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
 
 use bevy::prelude::*;
+use map::Theme;
 use std::path::PathBuf;
 
 const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
 
+const TITLE: &str = "PC Map Editor";
+
 fn main() {
     App::new()
-        .add_plugins(DefaultPlugins)
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                title: TITLE.into(),
+                ..default()
+            }),
+            ..default()
+        }))
         .add_state::<State>()
         .init_resource::<InFile>()
+        .insert_resource(load_theme())
         .add_systems(Startup, setup)
         .add_plugins((menu::MenuPlugin, editor::EditorPlugin))
         .run();
@@ -25,15 +35,40 @@ enum State {
 #[derive(Resource, Default)]
 struct InFile(Option<PathBuf>);
 
+const THEME_FILE: &str = "theme.json";
+
+fn load_theme() -> Theme {
+    if !std::path::Path::new(THEME_FILE).exists() {
+        return Theme::default();
+    }
+
+    Theme::load_from_path(THEME_FILE).unwrap_or_else(|error| {
+        println!("Failed to load {}: {}", THEME_FILE, error);
+        Theme::default()
+    })
+}
+
 fn setup(mut commands: Commands) {
     commands.spawn(Camera2dBundle::default());
 }
 
 mod menu {
-    use bevy::prelude::*;
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    use bevy::{
+        prelude::*,
+        render::{
+            render_resource::{Extent3d, TextureDimension, TextureFormat},
+            texture::ImageSampler,
+        },
+    };
+    use map::{Map, Theme};
     use rfd::FileDialog;
 
-    use super::{despawn, InFile, State, TEXT_COLOR};
+    use super::{despawn, InFile, State, TEXT_COLOR, TITLE};
 
     #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
     enum MenuState {
@@ -47,8 +82,15 @@ mod menu {
     impl Plugin for MenuPlugin {
         fn build(&self, app: &mut App) {
             app.add_state::<MenuState>()
+                .init_resource::<LoadDirectory>()
+                .init_resource::<Thumbnails>()
                 .add_systems(OnEnter(State::StartMenu), menu_setup)
-                .add_systems(OnEnter(MenuState::Main), main_menu_setup)
+                // a restored session can leave the start menu in the same frame the menu state starts,
+                // in which case the main menu would be spawned after its despawn and stay over the editor
+                .add_systems(
+                    OnEnter(MenuState::Main),
+                    main_menu_setup.run_if(in_state(State::StartMenu)),
+                )
                 .add_systems(OnEnter(MenuState::LoadMap), load_map_setup)
                 .add_systems(
                     Update,
@@ -76,9 +118,30 @@ mod menu {
         NewMap,
         LoadMap,
         FileSelect,
+        OpenFile(PathBuf),
         Continue,
     }
 
+    // Where the load menu looks for maps, follows the last file picked with the file dialog
+    #[derive(Resource)]
+    struct LoadDirectory(PathBuf);
+
+    impl Default for LoadDirectory {
+        fn default() -> Self {
+            Self(PathBuf::from("."))
+        }
+    }
+
+    // Map previews by content hash so reopening the load menu doesn't rasterize every map again
+    #[derive(Resource, Default)]
+    struct Thumbnails {
+        images: HashMap<u64, Handle<Image>>,
+        placeholder: Option<Handle<Image>>,
+    }
+
+    const THUMBNAIL_SIZE: f32 = 64.0;
+    const MAX_LISTED_MAPS: usize = 5;
+
     const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
     const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
     const HOVERED_PRESSED_BUTTON: Color = Color::rgb(0.25, 0.65, 0.25);
@@ -155,7 +218,7 @@ mod menu {
                         // Display the game name
                         parent.spawn(
                             TextBundle::from_section(
-                                "PC Map Editor",
+                                TITLE,
                                 TextStyle {
                                     font_size: 80.0,
                                     color: TEXT_COLOR,
@@ -206,7 +269,98 @@ mod menu {
             });
     }
 
-    fn load_map_setup(mut commands: Commands) {
+    fn map_files(directory: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(directory)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.extension()
+                            .is_some_and(|extension| extension == "map" || extension == "mapb")
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+
+    // The PNG export at one pixel per tile, so previews look like exported images.
+    // Textures can't be empty, so an empty map previews as a single blocked tile
+    fn rasterize(map: &Map, theme: &Theme) -> Image {
+        let buffer = if map.is_empty() {
+            Map::new_blocked(1, 1).as_image_buffer(1, theme)
+        } else {
+            map.as_image_buffer(1, theme)
+        };
+
+        let mut image = Image::new(
+            Extent3d {
+                width: buffer.width(),
+                height: buffer.height(),
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            buffer.into_raw(),
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        image.sampler_descriptor = ImageSampler::nearest();
+        image
+    }
+
+    fn thumbnail(
+        path: &Path,
+        thumbnails: &mut Thumbnails,
+        images: &mut Assets<Image>,
+        theme: &Theme,
+    ) -> Handle<Image> {
+        match Map::load_from_path(path) {
+            Ok(map) => thumbnails
+                .images
+                .entry(map.content_hash())
+                .or_insert_with(|| images.add(rasterize(&map, theme)))
+                .clone(),
+            Err(_) => thumbnails
+                .placeholder
+                .get_or_insert_with(|| images.add(rasterize(&Map::default(), theme)))
+                .clone(),
+        }
+    }
+
+    fn load_map_setup(
+        mut commands: Commands,
+        load_directory: Res<LoadDirectory>,
+        mut thumbnails: ResMut<Thumbnails>,
+        mut images: ResMut<Assets<Image>>,
+        theme: Res<Theme>,
+    ) {
+        let files: Vec<(String, PathBuf, Handle<Image>)> = map_files(&load_directory.0)
+            .into_iter()
+            .take(MAX_LISTED_MAPS)
+            .map(|path| {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let image = thumbnail(&path, &mut thumbnails, &mut images, &theme);
+                (name, path, image)
+            })
+            .collect();
+
+        let file_button_style = Style {
+            width: Val::Px(250.0),
+            height: Val::Px(THUMBNAIL_SIZE),
+            margin: UiRect::all(Val::Px(5.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        };
+        let file_text_style = TextStyle {
+            font_size: 25.0,
+            color: TEXT_COLOR,
+            ..default()
+        };
+
         // Common style for all buttons on the screen
         let button_style = Style {
             width: Val::Px(250.0),
@@ -247,6 +401,45 @@ mod menu {
                         ..default()
                     })
                     .with_children(|parent| {
+                        // Maps in the load directory with a preview next to each
+                        for (name, path, image) in files {
+                            parent
+                                .spawn(NodeBundle {
+                                    style: Style {
+                                        flex_direction: FlexDirection::Row,
+                                        align_items: AlignItems::Center,
+                                        ..default()
+                                    },
+                                    ..default()
+                                })
+                                .with_children(|parent| {
+                                    parent.spawn(ImageBundle {
+                                        style: Style {
+                                            width: Val::Px(THUMBNAIL_SIZE),
+                                            height: Val::Px(THUMBNAIL_SIZE),
+                                            ..default()
+                                        },
+                                        image: UiImage::new(image),
+                                        ..default()
+                                    });
+                                    parent
+                                        .spawn((
+                                            ButtonBundle {
+                                                style: file_button_style.clone(),
+                                                background_color: NORMAL_BUTTON.into(),
+                                                ..default()
+                                            },
+                                            MenuAction::OpenFile(path),
+                                        ))
+                                        .with_children(|parent| {
+                                            parent.spawn(TextBundle::from_section(
+                                                name,
+                                                file_text_style.clone(),
+                                            ));
+                                        });
+                                });
+                        }
+
                         parent
                             .spawn((
                                 ButtonBundle {
@@ -301,6 +494,7 @@ mod menu {
         mut menu_state: ResMut<NextState<MenuState>>,
         mut game_state: ResMut<NextState<State>>,
         mut in_file: ResMut<InFile>,
+        mut load_directory: ResMut<LoadDirectory>,
     ) {
         for (interaction, action) in &interaction_query {
             if *interaction == Interaction::Pressed {
@@ -312,9 +506,15 @@ mod menu {
                     MenuAction::NewMap => game_state.set(State::Editor),
                     MenuAction::LoadMap => menu_state.set(MenuState::LoadMap),
                     MenuAction::FileSelect => {
-                        let file = FileDialog::new().add_filter("map", &["map"]).pick_file();
+                        let file = FileDialog::new()
+                            .add_filter("map", &["map", "mapb"])
+                            .pick_file();
+                        if let Some(directory) = file.as_ref().and_then(|file| file.parent()) {
+                            load_directory.0 = directory.to_path_buf();
+                        }
                         in_file.0 = file;
                     }
+                    MenuAction::OpenFile(path) => in_file.0 = Some(path.clone()),
                     MenuAction::Continue => {
                         if in_file.0.is_some() {
                             game_state.set(State::Editor);
@@ -324,19 +524,70 @@ mod menu {
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn thumbnails_match_the_png_export() {
+            let theme = Theme::default();
+            let map = Map::from_ascii("#.D\n~^.\n").unwrap();
+
+            let thumbnail = rasterize(&map, &theme);
+            assert_eq!(thumbnail.size(), Vec2::new(3.0, 2.0));
+            assert_eq!(thumbnail.data, map.as_image_buffer(1, &theme).into_raw());
+
+            // empty maps still need a texture
+            let empty = rasterize(&Map::default(), &theme);
+            assert_eq!(empty.size(), Vec2::ONE);
+        }
+
+        fn main_menus(app: &mut App) -> usize {
+            app.world
+                .query_filtered::<(), With<OnMainMenu>>()
+                .iter(&app.world)
+                .count()
+        }
+
+        #[test]
+        fn restored_sessions_skip_the_main_menu() {
+            let mut app = App::new();
+            app.add_state::<State>()
+                .init_resource::<InFile>()
+                .add_plugins(MenuPlugin);
+            app.update();
+            assert!(main_menus(&mut app) > 0);
+
+            let mut app = App::new();
+            app.add_state::<State>()
+                .init_resource::<InFile>()
+                .add_plugins(MenuPlugin);
+            app.world
+                .resource_mut::<NextState<State>>()
+                .set(State::Editor);
+            app.update();
+            app.update();
+            assert_eq!(main_menus(&mut app), 0);
+        }
+    }
 }
 
 mod editor {
-    use std::{fs::File, io::Write};
+    use std::{
+        collections::VecDeque,
+        f32::consts::FRAC_PI_2,
+        path::{Path, PathBuf},
+    };
 
-    use super::{despawn, InFile, State};
-    use bevy::{
-        input::mouse::{MouseMotion, MouseWheel},
-        prelude::*,
-        text::Text2dBounds,
+    use super::{despawn, InFile, State, TEXT_COLOR, TITLE};
+    use bevy::{app::AppExit, input::mouse::MouseWheel, prelude::*, window::ReceivedCharacter};
+    use map::{
+        line_points, Connection, ConnectionError, Direction, Map, Object, ObjectType, Theme, Tile,
+        TileSize, TileType,
     };
-    use map::{Map, Object, ObjectType, Tile, TileType};
-    use rfd::FileDialog;
+    use rfd::{FileDialog, MessageButtons, MessageDialog};
+    use serde::{Deserialize, Serialize};
 
     #[derive(Component)]
     struct TileComponent;
@@ -346,229 +597,2659 @@ mod editor {
     #[derive(Resource, Default)]
     struct LiveMap(Map);
 
+    // The file the live map was loaded from or last saved to, and its content hash at that point
+    #[derive(Resource, Default)]
+    struct CurrentFile {
+        path: Option<PathBuf>,
+        saved_hash: u64,
+    }
+
+    // Snapshots of the live map for undo and redo, only the newest MAX_HISTORY steps are kept
+    #[derive(Resource, Default)]
+    struct EditHistory {
+        undo: VecDeque<Map>,
+        redo: Vec<Map>,
+        // the map as of the last recorded step
+        current: Map,
+    }
+
+    const MAX_HISTORY: usize = 100;
+
+    impl EditHistory {
+        fn reset(&mut self, map: &Map) {
+            self.undo.clear();
+            self.redo.clear();
+            self.current = map.clone();
+        }
+
+        // A map that differs from the last step becomes a new step and drops everything that was undone
+        fn record(&mut self, map: &Map) {
+            if *map == self.current {
+                return;
+            }
+
+            self.undo
+                .push_back(std::mem::replace(&mut self.current, map.clone()));
+            if self.undo.len() > MAX_HISTORY {
+                self.undo.pop_front();
+            }
+            self.redo.clear();
+        }
+
+        fn undo(&mut self) -> Option<&Map> {
+            let previous = self.undo.pop_back()?;
+            self.redo
+                .push(std::mem::replace(&mut self.current, previous));
+            Some(&self.current)
+        }
+
+        fn redo(&mut self) -> Option<&Map> {
+            let next = self.redo.pop()?;
+            self.undo
+                .push_back(std::mem::replace(&mut self.current, next));
+            Some(&self.current)
+        }
+    }
+
     #[derive(Component)]
     struct Highlighted;
 
-    const TILE_SIZE: f32 = 256.0;
+    // Tiles clicked this frame as (x, y), possibly outside the map until the stroke is applied
+    #[derive(Resource, Default)]
+    struct PaintStroke(Vec<(i32, i32)>);
 
-    #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
-    enum DrawState {
-        #[default]
-        Refresh,
-        Update,
+    // Tile under the cursor as of the last frame of a left drag as (x, y), None while the button is up
+    #[derive(Resource, Default)]
+    struct LastPainted(Option<(i32, i32)>);
+
+    // Tile picked with Shift + left click as (x, y)
+    #[derive(Resource, Default)]
+    struct SelectedTile(Option<(usize, usize)>);
+
+    // A link whose target map is picked, waiting for the spawn to be typed. Editing is paused while it's open
+    #[derive(Resource, Default)]
+    struct PendingLink(Option<LinkEntry>);
+
+    struct LinkEntry {
+        at: (usize, usize),
+        path: PathBuf,
+        target: Map,
+        // "x, y" as typed so far, starting from the target's default spawn
+        spawn: String,
     }
 
-    impl Plugin for EditorPlugin {
-        fn build(&self, app: &mut App) {
-            app.add_state::<DrawState>()
-                .add_systems(OnEnter(State::Editor), editor_setup)
-                .init_resource::<LiveMap>()
-                .add_systems(
-                    Update,
-                    (mouse_navigation, mouse_input, keyboard_input)
-                        .chain()
-                        .run_if(in_state(State::Editor)),
-                )
-                .add_systems(
-                    OnEnter(DrawState::Refresh),
-                    (despawn::<TileComponent>, refresh_map).chain(),
-                );
+    #[derive(Component)]
+    struct LinkPrompt;
+
+    // Pixels per tile in exported PNGs
+    const PNG_BLOCK_SIZE: u32 = 16;
+
+    // Tile the keyboard cursor is on as (x, y), it can leave the map to grow it like a click would
+    #[derive(Resource, Default)]
+    struct CursorTile((i32, i32));
+
+    #[derive(Component)]
+    struct CursorHighlight;
+
+    const CURSOR_COLOR: Color = Color::rgba(0.2, 0.6, 1.0, 0.4);
+
+    const SELECTION_COLOR: Color = Color::rgba(1.0, 0.9, 0.2, 0.4);
+
+    // World position grabbed when the right mouse button went down
+    #[derive(Resource, Default)]
+    struct PanAnchor(Option<Vec2>);
+
+    #[derive(Resource)]
+    struct MapLimits {
+        max_dimension: usize,
+    }
+
+    impl Default for MapLimits {
+        fn default() -> Self {
+            Self {
+                max_dimension: map::DEFAULT_MAX_DIMENSION,
+            }
         }
     }
 
-    fn editor_setup(
-        mut in_file: ResMut<InFile>,
-        mut map: ResMut<LiveMap>,
-        mut draw_state: ResMut<NextState<DrawState>>,
-    ) {
-        let mut m = map::Map { tiles: Vec::new() };
-        if let Some(file) = in_file.0.take() {
-            let file = std::fs::File::open(file).unwrap();
+    // Range the camera scale stays in, min has to be above zero so zooming can't flip the view
+    #[derive(Resource)]
+    struct CameraLimits {
+        min_zoom: f32,
+        max_zoom: f32,
+    }
 
-            if let Ok(file_map) = serde_json::from_reader(file) {
-                m = file_map;
-            } else {
-                println!("Failed to load map from file");
+    impl Default for CameraLimits {
+        fn default() -> Self {
+            Self {
+                min_zoom: 0.1,
+                max_zoom: 50.0,
             }
         }
+    }
 
-        map.0 = m;
+    impl CameraLimits {
+        fn clamp(&self, zoom: f32) -> f32 {
+            zoom.clamp(self.min_zoom, self.max_zoom)
+        }
 
-        draw_state.set(DrawState::Refresh);
+        // Scale after `scroll` wheel ticks, each zooming out by a tenth of the current scale
+        fn scrolled(&self, zoom: f32, scroll: f32) -> f32 {
+            self.clamp(zoom + scroll * zoom * 0.1)
+        }
     }
 
-    fn refresh_map(
-        commands: Commands,
-        map: Res<LiveMap>,
-        mut draw_state: ResMut<NextState<DrawState>>,
-    ) {
-        render_map(commands, &map.0);
+    const SESSION_FILE: &str = "editor_session.json";
 
-        draw_state.set(DrawState::Update);
+    // Where editing left off, kept apart from the map itself
+    #[derive(Serialize, Deserialize)]
+    struct Session {
+        file: PathBuf,
+        camera: [f32; 2],
+        zoom: f32,
     }
 
-    fn render_map(mut commands: Commands, map: &map::Map) {
-        for (i, row) in map.tiles.iter().enumerate() {
-            for (j, tile) in row.iter().enumerate() {
-                render_tile(&mut commands, j, i, tile);
+    // Session loaded at startup, applied to the camera once the editor opens
+    #[derive(Resource, Default)]
+    struct RestoredSession(Option<Session>);
+
+    const DEFAULT_TILE_SIZE: f32 = 256.0;
+    // (width, height) of the blocked canvas a new map starts from
+    const NEW_MAP_SIZE: (usize, usize) = (16, 16);
+
+    // fraction of the shorter tile side
+    const CONNECTION_BORDER: f32 = 0.08;
+    const CONNECTION_COLOR: Color = Color::PURPLE;
+    const BROKEN_CONNECTION_COLOR: Color = Color::RED;
+
+    // Tiles whose connection failed the last validation, as (x, y)
+    #[derive(Resource, Default)]
+    struct BrokenConnections(Vec<(usize, usize)>);
+
+    #[derive(Resource, Default)]
+    struct ConnectionOverlay(bool);
+
+    #[derive(Component)]
+    struct ConnectionMarker;
+
+    #[derive(Resource, Default)]
+    struct CoordinateOverlay(bool);
+
+    // Lines along every tile edge, drawn in world space so they zoom with the map
+    #[derive(Resource, Default)]
+    struct GridOverlay(bool);
+
+    #[derive(Component)]
+    struct GridLine;
+
+    const GRID_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.35);
+    const GRID_LINE_WIDTH: f32 = 0.02;
+
+    // Objects are bars along the edge they face and floor objects a square in the middle, both as a
+    // fraction of the tile
+    const OBJECT_THICKNESS: f32 = 0.2;
+    const FLOOR_OBJECT_SIZE: f32 = 0.4;
+
+    #[derive(Resource, Clone, Copy, Default, Eq, PartialEq, Debug)]
+    enum Tool {
+        #[default]
+        Paint,
+        Wall,
+        Erase,
+        Path,
+        Door,
+        Link,
+    }
+
+    impl Tool {
+        const ALL: [Tool; 6] = [
+            Tool::Paint,
+            Tool::Wall,
+            Tool::Erase,
+            Tool::Path,
+            Tool::Door,
+            Tool::Link,
+        ];
+
+        fn next(self) -> Tool {
+            let index = Tool::ALL.iter().position(|tool| *tool == self).unwrap_or(0);
+            Tool::ALL[(index + 1) % Tool::ALL.len()]
+        }
+
+        fn name(&self) -> &'static str {
+            match self {
+                Tool::Paint => "Paint",
+                Tool::Wall => "Wall",
+                Tool::Erase => "Erase",
+                Tool::Path => "Path",
+                Tool::Door => "Door",
+                Tool::Link => "Link",
             }
         }
+
+        fn places_object(&self) -> bool {
+            matches!(self, Tool::Wall | Tool::Door)
+        }
+
+        // Tools that act on single clicks instead of painting while dragged
+        fn takes_clicks(&self) -> bool {
+            matches!(self, Tool::Path | Tool::Link)
+        }
     }
 
-    fn render_tile(commands: &mut Commands, x: usize, y: usize, tile: &Tile) {
-        commands
-            .spawn((
-                SpriteBundle {
-                    sprite: Sprite {
-                        color: match tile.tile_type {
-                            TileType::Walkable => Color::WHITE,
-                            TileType::Blocked => Color::GRAY,
-                        },
-                        custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
-                        ..default()
-                    },
-                    transform: Transform::from_translation(Vec3::new(
-                        x as f32 * TILE_SIZE,
-                        y as f32 * TILE_SIZE,
-                        0.0,
-                    )),
-                    ..default()
-                },
-                TileComponent,
-            ))
-            .with_children(|parent| {
-                let mut text = Vec::new();
-                let text_style = TextStyle {
-                    font_size: 75.0,
-                    color: Color::BLACK,
-                    ..default()
-                };
+    #[derive(Component)]
+    struct ToolIndicator;
 
-                if let Some(object) = &tile.object {
-                    text.push(TextSection {
-                        value: object.object_type.to_string(),
-                        style: text_style.clone(),
-                    });
-                }
+    // Which way the wall and door tools face what they place, R turns it clockwise
+    #[derive(Resource)]
+    struct PlacementFacing(Direction);
 
-                if let Some(floor_object) = &tile.floor_object {
-                    text.push(TextSection {
-                        value: floor_object.object_type.to_string(),
-                        style: text_style.clone(),
-                    });
-                }
+    impl Default for PlacementFacing {
+        fn default() -> Self {
+            PlacementFacing(Direction::North)
+        }
+    }
 
-                if let Some(connection) = &tile.connection {
-                    text.push(TextSection {
-                        value: connection.to_string(),
-                        style: text_style,
-                    });
-                }
+    // Button in the tool palette that picks its tool
+    #[derive(Component)]
+    struct ToolButton(Tool);
 
-                parent.spawn(Text2dBundle {
-                    text: Text {
-                        sections: text,
-                        alignment: TextAlignment::Center,
-                        ..default()
-                    },
-                    text_2d_bounds: Text2dBounds {
-                        size: Vec2::new(TILE_SIZE, TILE_SIZE),
-                    },
-                    transform: Transform::from_translation(Vec3::Z),
-                    ..default()
-                });
-            });
+    const PALETTE_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+    const PALETTE_HOVERED_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+    const PALETTE_SELECTED_COLOR: Color = Color::rgb(0.35, 0.75, 0.35);
+
+    // Ends picked with the path tool as (x, y) and the shortest path between them once both are set
+    #[derive(Resource, Default)]
+    struct PathPreview {
+        start: Option<(usize, usize)>,
+        goal: Option<(usize, usize)>,
+        path: Option<Vec<(usize, usize)>>,
     }
 
-    fn mouse_navigation(
-        mut camera: Query<&mut Transform, With<Camera>>,
-        mouse_input: Res<Input<MouseButton>>,
-        mut cursor: EventReader<MouseMotion>,
-        mut scroll: EventReader<MouseWheel>,
-    ) {
-        if let Ok(mut camera) = camera.get_single_mut() {
-            if mouse_input.pressed(MouseButton::Right) {
-                for event in cursor.iter() {
-                    camera.translation.x -= event.delta.x * camera.scale.x * 0.2;
-                    camera.translation.y += event.delta.y * camera.scale.y * 0.2;
-                }
+    impl PathPreview {
+        fn status(&self) -> String {
+            match (self.start, self.goal, &self.path) {
+                (None, _, _) => "pick a start".to_string(),
+                (Some(_), None, _) => "pick a goal".to_string(),
+                (Some(_), Some(_), Some(path)) => format!("{} steps", path.len() - 1),
+                (Some(_), Some(_), None) => "no path".to_string(),
             }
+        }
+    }
 
-            for event in scroll.iter() {
-                camera.scale.x += event.y * camera.scale.x * 0.1;
-                camera.scale.y += event.y * camera.scale.y * 0.1;
+    const PATH_COLOR: Color = Color::rgba(0.1, 0.8, 0.3, 0.5);
+
+    // Every keyboard shortcut of the edit mode, the keybind overlay is built from this list
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum EditorAction {
+        CycleTool,
+        ValidateConnections,
+        Playtest,
+        ToggleConnections,
+        ToggleCoordinates,
+        ToggleGrid,
+        ExportObj,
+        ExportPng,
+        BorderWalls,
+        Save,
+        SaveAs,
+        ClearSelected,
+        Undo,
+        Redo,
+        RotateObject,
+        LinkSelected,
+        Keybinds,
+    }
+
+    impl EditorAction {
+        const ALL: [EditorAction; 17] = [
+            EditorAction::CycleTool,
+            EditorAction::ValidateConnections,
+            EditorAction::Playtest,
+            EditorAction::ToggleConnections,
+            EditorAction::ToggleCoordinates,
+            EditorAction::ToggleGrid,
+            EditorAction::ExportObj,
+            EditorAction::ExportPng,
+            EditorAction::BorderWalls,
+            EditorAction::Save,
+            EditorAction::SaveAs,
+            EditorAction::ClearSelected,
+            EditorAction::Undo,
+            EditorAction::Redo,
+            EditorAction::RotateObject,
+            EditorAction::LinkSelected,
+            EditorAction::Keybinds,
+        ];
+
+        fn key(&self) -> KeyCode {
+            match self {
+                EditorAction::CycleTool => KeyCode::Tab,
+                EditorAction::ValidateConnections => KeyCode::V,
+                EditorAction::Playtest => KeyCode::P,
+                EditorAction::ToggleConnections => KeyCode::C,
+                EditorAction::ToggleCoordinates => KeyCode::X,
+                EditorAction::ToggleGrid => KeyCode::G,
+                EditorAction::ExportObj => KeyCode::O,
+                EditorAction::ExportPng => KeyCode::I,
+                EditorAction::BorderWalls => KeyCode::B,
+                EditorAction::Save | EditorAction::SaveAs => KeyCode::S,
+                EditorAction::ClearSelected => KeyCode::Delete,
+                EditorAction::Undo => KeyCode::Z,
+                EditorAction::Redo => KeyCode::Y,
+                EditorAction::RotateObject => KeyCode::R,
+                EditorAction::LinkSelected => KeyCode::L,
+                EditorAction::Keybinds => KeyCode::Slash,
             }
         }
-    }
 
-    fn mouse_input(
-        mut commands: Commands,
-        mut camera: Query<&mut Transform, With<Camera>>,
-        mut map: ResMut<LiveMap>,
-        mouse_input: Res<Input<MouseButton>>,
-        windows: Query<&Window>,
-        mut draw_state: ResMut<NextState<DrawState>>,
-    ) {
-        if let Ok(mut camera) = camera.get_single_mut() {
-            if let Ok(window) = windows.get_single() {
-                if let Some(position) = window.cursor_position() {
-                    let x = position.x - window.width() * 0.5;
-                    let y = window.height() * 0.5 - position.y;
+        fn binding(&self) -> &'static str {
+            match self {
+                EditorAction::CycleTool => "Tab",
+                EditorAction::ValidateConnections => "V",
+                EditorAction::Playtest => "P",
+                EditorAction::ToggleConnections => "C",
+                EditorAction::ToggleCoordinates => "X",
+                EditorAction::ToggleGrid => "G",
+                EditorAction::ExportObj => "O",
+                EditorAction::ExportPng => "I",
+                EditorAction::BorderWalls => "B",
+                EditorAction::Save => "S",
+                EditorAction::SaveAs => "Shift + S",
+                EditorAction::ClearSelected => "Delete",
+                EditorAction::Undo => "Ctrl + Z",
+                EditorAction::Redo => "Ctrl + Y",
+                EditorAction::RotateObject => "R",
+                EditorAction::LinkSelected => "L",
+                EditorAction::Keybinds => "?",
+            }
+        }
 
-                    let x = x + camera.translation.x;
-                    let y = y + camera.translation.y;
+        fn description(&self) -> &'static str {
+            match self {
+                EditorAction::CycleTool => "Next tool",
+                EditorAction::ValidateConnections => "Validate connections",
+                EditorAction::Playtest => "Playtest the map",
+                EditorAction::ToggleConnections => "Show connections",
+                EditorAction::ToggleCoordinates => "Show coordinates",
+                EditorAction::ToggleGrid => "Show the tile grid",
+                EditorAction::ExportObj => "Export OBJ",
+                EditorAction::ExportPng => "Export PNG",
+                EditorAction::BorderWalls => "Wall off the border",
+                EditorAction::Save => "Save",
+                EditorAction::SaveAs => "Save as",
+                EditorAction::ClearSelected => "Clear the selected tile",
+                EditorAction::Undo => "Undo",
+                EditorAction::Redo => "Redo",
+                EditorAction::RotateObject => "Turn placed walls and doors",
+                EditorAction::LinkSelected => "Link the selected tile to a map",
+                EditorAction::Keybinds => "Show this list",
+            }
+        }
 
-                    let x = x * camera.scale.x;
-                    let y = y * camera.scale.y;
+        fn just_pressed(&self, keyboard_input: &Input<KeyCode>) -> bool {
+            keyboard_input.just_pressed(self.key())
+                && match self {
+                    // ? is shift + slash
+                    EditorAction::Keybinds | EditorAction::SaveAs => shift_held(keyboard_input),
+                    EditorAction::Save => !shift_held(keyboard_input),
+                    EditorAction::Undo | EditorAction::Redo => modifier_held(keyboard_input),
+                    _ => true,
+                }
+        }
+    }
 
-                    let x = x.floor() / TILE_SIZE;
-                    let y = y.floor() / TILE_SIZE;
+    // Bindings that aren't a single key press, listed after the actions
+    const OTHER_BINDINGS: [(&str, &str); 8] = [
+        ("1-6", "Pick a tool"),
+        ("Left click / drag", "Use the tool"),
+        ("Shift + click", "Select a tile"),
+        ("Right drag", "Pan"),
+        ("Wheel", "Zoom"),
+        ("Ctrl + wheel", "Cycle the hovered tile"),
+        ("Arrows", "Move the cursor"),
+        ("Space / Enter", "Use the tool at the cursor"),
+    ];
 
-                    if mouse_input.just_pressed(MouseButton::Left) {
-                        map.0.expand_to(x as i32, y as i32);
+    // Whether the keybind list is open, editing is paused while it is
+    #[derive(Resource, Default)]
+    struct KeybindsOpen(bool);
 
-                        if let Some(tile) = map.0.tiles.get_mut(y as usize) {
-                            if let Some(tile) = tile.get_mut(x as usize) {
-                                tile.tile_type = TileType::Walkable;
-                                tile.object = Some(Object {
-                                    object_type: ObjectType::Wall,
-                                    rotation: Quat::IDENTITY,
-                                });
-                                render_tile(&mut commands, x as usize, y as usize, tile);
-                            }
-                        }
+    #[derive(Component)]
+    struct KeybindOverlay;
 
-                        if x < 0.0 {
-                            camera.translation.x = -((window.cursor_position().unwrap().x
-                                - window.width() * 0.5)
-                                * camera.scale.x);
-                        }
-                        if y < 0.0 {
-                            camera.translation.y = -((window.height() * 0.5
-                                - window.cursor_position().unwrap().y)
-                                * camera.scale.y);
-                        }
+    // Something the user tried failed, shown in the ErrorBanner for ERROR_SECONDS and logged.
+    // The banner is up in the menu too, for files the editor refuses to open
+    #[derive(Event, Clone, Debug, PartialEq)]
+    struct EditorError(String);
 
-                        draw_state.set(DrawState::Refresh);
-                    }
-                }
-            }
+    impl EditorError {
+        fn file(action: &str, path: &Path, error: impl std::fmt::Display) -> Self {
+            EditorError(format!(
+                "Failed to {} {}: {}",
+                action,
+                path.display(),
+                error
+            ))
         }
     }
 
-    fn keyboard_input(map: ResMut<LiveMap>, keyboard_input: Res<Input<KeyCode>>) {
-        if keyboard_input.just_pressed(KeyCode::S) {
-            let file_dialog = FileDialog::new().add_filter("Map", &["map"]).save_file();
+    #[derive(Component)]
+    struct ErrorBanner;
+
+    const ERROR_SECONDS: f32 = 4.0;
+    const ERROR_COLOR: Color = Color::rgb(1.0, 0.4, 0.4);
 
-            if let Some(path) = file_dialog {
-                let mut file = File::create(path).unwrap();
-                let mut map = map.0.clone();
-                map.trim();
-                map.pad(1);
+    // Details of the hovered tile, following the cursor
+    #[derive(Component)]
+    struct HoverTooltip;
 
-                let map = serde_json::to_string(&map).unwrap();
-                file.write_all(map.as_bytes()).unwrap();
-            }
+    const TOOLTIP_OFFSET: Vec2 = Vec2::new(16.0, 16.0);
+
+    // Play walks the live map in place so unsaved edits are kept when returning to Edit
+    #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
+    enum EditorMode {
+        #[default]
+        Edit,
+        Play,
+    }
+
+    #[derive(Component)]
+    struct PlaytestPlayer;
+
+    // Tile the playtest player stands on as (x, y)
+    #[derive(Resource, Default)]
+    struct PlaytestPosition((usize, usize));
+
+    #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
+    enum DrawState {
+        #[default]
+        Refresh,
+        Update,
+    }
+
+    impl Plugin for EditorPlugin {
+        fn build(&self, app: &mut App) {
+            app.add_state::<DrawState>()
+                .add_state::<EditorMode>()
+                .init_resource::<PlaytestPosition>()
+                .add_systems(OnEnter(State::Editor), editor_setup)
+                .init_resource::<LiveMap>()
+                .init_resource::<MapLimits>()
+                .init_resource::<CameraLimits>()
+                .init_resource::<ConnectionOverlay>()
+                .init_resource::<BrokenConnections>()
+                .init_resource::<CoordinateOverlay>()
+                .init_resource::<GridOverlay>()
+                .init_resource::<Tool>()
+                .init_resource::<CurrentFile>()
+                .init_resource::<PanAnchor>()
+                .init_resource::<SelectedTile>()
+                .init_resource::<PendingLink>()
+                .init_resource::<PaintStroke>()
+                .init_resource::<LastPainted>()
+                .init_resource::<PlacementFacing>()
+                .add_event::<EditorError>()
+                .init_resource::<CursorTile>()
+                .init_resource::<KeybindsOpen>()
+                .init_resource::<PathPreview>()
+                .init_resource::<EditHistory>()
+                .insert_resource(TileSize::square(DEFAULT_TILE_SIZE))
+                .init_resource::<RestoredSession>()
+                .add_systems(Startup, restore_session)
+                .add_systems(Last, save_session)
+                .add_systems(
+                    Update,
+                    (
+                        tool_select,
+                        mouse_navigation,
+                        cycle_hovered_tile,
+                        tile_selection,
+                        mouse_input,
+                        keyboard_cursor,
+                        pick_path_ends,
+                        link_selected_tile,
+                        apply_paint_stroke,
+                        keyboard_input,
+                        edit_history,
+                        update_tool_indicator,
+                        update_tooltip,
+                        update_title,
+                    )
+                        .chain()
+                        .run_if(in_state(State::Editor))
+                        .run_if(in_state(EditorMode::Edit))
+                        .run_if(keybinds_closed)
+                        .run_if(no_pending_link),
+                )
+                .add_systems(
+                    Update,
+                    (link_entry, update_link_prompt)
+                        .chain()
+                        .run_if(in_state(State::Editor))
+                        .run_if(in_state(EditorMode::Edit)),
+                )
+                .add_systems(
+                    Update,
+                    toggle_keybinds
+                        .run_if(in_state(State::Editor))
+                        .run_if(in_state(EditorMode::Edit))
+                        .run_if(no_pending_link),
+                )
+                .add_systems(Startup, error_banner_setup)
+                .add_systems(Update, show_errors)
+                .add_systems(OnEnter(EditorMode::Play), playtest_setup)
+                .add_systems(
+                    Update,
+                    playtest_input
+                        .run_if(in_state(State::Editor))
+                        .run_if(in_state(EditorMode::Play)),
+                )
+                .add_systems(OnExit(EditorMode::Play), despawn::<PlaytestPlayer>)
+                .add_systems(
+                    OnEnter(DrawState::Refresh),
+                    (despawn::<TileComponent>, refresh_map).chain(),
+                );
+        }
+    }
+
+    fn editor_setup(
+        mut commands: Commands,
+        tool: Res<Tool>,
+        mut in_file: ResMut<InFile>,
+        mut map: ResMut<LiveMap>,
+        mut current_file: ResMut<CurrentFile>,
+        mut history: ResMut<EditHistory>,
+        mut errors: EventWriter<EditorError>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+        mut game_state: ResMut<NextState<State>>,
+        mut restored: ResMut<RestoredSession>,
+        mut camera: Query<&mut Transform, With<Camera>>,
+        camera_limits: Res<CameraLimits>,
+    ) {
+        if let (Some(session), Ok(mut camera)) = (restored.0.take(), camera.get_single_mut()) {
+            let [x, y] = session.camera;
+            if x.is_finite() && y.is_finite() {
+                camera.translation.x = x;
+                camera.translation.y = y;
+            }
+
+            let zoom = if session.zoom.is_finite() {
+                camera_limits.clamp(session.zoom)
+            } else {
+                1.0
+            };
+            camera.scale.x = zoom;
+            camera.scale.y = zoom;
+        }
+
+        let mut m = Map::new_blocked(NEW_MAP_SIZE.0, NEW_MAP_SIZE.1);
+        let mut path = None;
+        if let Some(in_path) = in_file.0.take() {
+            match load_map(&in_path) {
+                Ok(file_map) if file_map.is_empty() => {
+                    errors.send(EditorError(format!(
+                        "Refusing to edit {}, it has no tiles",
+                        in_path.display()
+                    )));
+                    game_state.set(State::StartMenu);
+                    return;
+                }
+                Ok(file_map) => {
+                    m = file_map;
+                    path = Some(in_path);
+                }
+                Err(error) => errors.send(EditorError::file("load", &in_path, error)),
+            }
+        }
+
+        *current_file = CurrentFile {
+            path,
+            saved_hash: m.content_hash(),
+        };
+        history.reset(&m);
+        map.0 = m;
+
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: CURSOR_COLOR,
+                    ..default()
+                },
+                ..default()
+            },
+            CursorHighlight,
+        ));
+
+        commands.spawn((
+            TextBundle::from_section(
+                format!("Tool: {}", tool.name()),
+                TextStyle {
+                    font_size: 30.0,
+                    color: TEXT_COLOR,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            }),
+            ToolIndicator,
+        ));
+
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(10.0),
+                    left: Val::Px(10.0),
+                    column_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|parent| {
+                for tool in Tool::ALL {
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: Style {
+                                    padding: UiRect::all(Val::Px(8.0)),
+                                    ..default()
+                                },
+                                background_color: PALETTE_COLOR.into(),
+                                ..default()
+                            },
+                            ToolButton(tool),
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                tool.name(),
+                                TextStyle {
+                                    font_size: 20.0,
+                                    color: TEXT_COLOR,
+                                    ..default()
+                                },
+                            ));
+                        });
+                }
+            });
+
+        commands.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                ..TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 20.0,
+                        color: TEXT_COLOR,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    padding: UiRect::all(Val::Px(4.0)),
+                    ..default()
+                })
+                .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.75))
+            },
+            HoverTooltip,
+        ));
+
+        commands.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                ..TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 24.0,
+                        color: TEXT_COLOR,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(10.0),
+                    left: Val::Percent(10.0),
+                    padding: UiRect::all(Val::Px(16.0)),
+                    ..default()
+                })
+                .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.85))
+            },
+            LinkPrompt,
+        ));
+
+        let keybinds = EditorAction::ALL
+            .iter()
+            .map(|action| (action.binding(), action.description()))
+            .chain(OTHER_BINDINGS)
+            .map(|(binding, description)| format!("{:<16}{}", binding, description))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        commands.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                ..TextBundle::from_section(
+                    keybinds,
+                    TextStyle {
+                        font_size: 24.0,
+                        color: TEXT_COLOR,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(10.0),
+                    left: Val::Percent(10.0),
+                    padding: UiRect::all(Val::Px(16.0)),
+                    ..default()
+                })
+                .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.85))
+            },
+            KeybindOverlay,
+        ));
+
+        draw_state.set(DrawState::Refresh);
+    }
+
+    // Offers the backup left by the last save when the file itself is corrupt
+    fn load_map(path: &Path) -> Result<Map, map::MapError> {
+        let error = match Map::load_from_path(path) {
+            Ok(map) => return Ok(map),
+            Err(error) => error,
+        };
+
+        let backup = map::backup_path(path);
+        if !backup.exists() {
+            return Err(error);
+        }
+
+        let use_backup = MessageDialog::new()
+            .set_title("Map could not be loaded")
+            .set_description(&format!(
+                "{}\n\nLoad the backup from the previous save instead?",
+                error
+            ))
+            .set_buttons(MessageButtons::YesNo)
+            .show();
+
+        if use_backup {
+            Map::load_from_path(backup)
+        } else {
+            Err(error)
+        }
+    }
+
+    // Reopens the file from the last session, staying on the menu if it's gone
+    fn restore_session(
+        mut in_file: ResMut<InFile>,
+        mut restored: ResMut<RestoredSession>,
+        mut game_state: ResMut<NextState<State>>,
+    ) {
+        let Ok(contents) = std::fs::read_to_string(SESSION_FILE) else {
+            return;
+        };
+
+        let session: Session = match serde_json::from_str(&contents) {
+            Ok(session) => session,
+            Err(error) => {
+                println!("Failed to parse {}: {}", SESSION_FILE, error);
+                return;
+            }
+        };
+
+        if !session.file.exists() {
+            println!(
+                "Last session's map {} no longer exists",
+                session.file.display()
+            );
+            return;
+        }
+
+        in_file.0 = Some(session.file.clone());
+        restored.0 = Some(session);
+        game_state.set(State::Editor);
+    }
+
+    fn save_session(
+        mut exit: EventReader<AppExit>,
+        current_file: Res<CurrentFile>,
+        camera: Query<&Transform, With<Camera>>,
+    ) {
+        if exit.iter().next().is_none() {
+            return;
+        }
+
+        // an untitled map can't be reopened, and leaving the old session would reopen an older map
+        let Some(file) = &current_file.path else {
+            match std::fs::remove_file(SESSION_FILE) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                    println!("Failed to remove {}: {}", SESSION_FILE, error)
+                }
+                _ => {}
+            }
+            return;
+        };
+        let Ok(camera) = camera.get_single() else {
+            return;
+        };
+
+        let session = Session {
+            file: file.clone(),
+            camera: [camera.translation.x, camera.translation.y],
+            zoom: camera.scale.x,
+        };
+
+        let result = serde_json::to_string_pretty(&session)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| std::fs::write(SESSION_FILE, contents));
+
+        if let Err(error) = result {
+            println!("Failed to save {}: {}", SESSION_FILE, error);
+        }
+    }
+
+    fn refresh_map(
+        mut commands: Commands,
+        map: Res<LiveMap>,
+        overlay: Res<ConnectionOverlay>,
+        broken: Res<BrokenConnections>,
+        coordinates: Res<CoordinateOverlay>,
+        grid: Res<GridOverlay>,
+        theme: Res<Theme>,
+        selected: Res<SelectedTile>,
+        tile_size: Res<TileSize>,
+        tool: Res<Tool>,
+        preview: Res<PathPreview>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        let tile_size = *tile_size;
+        render_map(&mut commands, &map.0, &theme, tile_size);
+
+        if *tool == Tool::Path {
+            let ends = preview.start.iter().chain(&preview.goal);
+            for &(x, y) in preview.path.iter().flatten().chain(ends) {
+                commands.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: PATH_COLOR,
+                            custom_size: Some(tile_size.as_vec2()),
+                            ..default()
+                        },
+                        transform: Transform::from_translation(
+                            tile_size.tile_to_world(x as i32, y as i32).extend(1.4),
+                        ),
+                        ..default()
+                    },
+                    TileComponent,
+                ));
+            }
+        }
+
+        if let Some((x, y)) = selected.0 {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: SELECTION_COLOR,
+                        custom_size: Some(tile_size.as_vec2()),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(
+                        tile_size.tile_to_world(x as i32, y as i32).extend(1.5),
+                    ),
+                    ..default()
+                },
+                Highlighted,
+                TileComponent,
+            ));
+        }
+
+        if overlay.0 {
+            render_connection_markers(&mut commands, &map.0, &broken.0, tile_size);
+        }
+
+        if coordinates.0 {
+            render_coordinates(&mut commands, &map.0, tile_size);
+        }
+
+        if grid.0 {
+            render_grid(&mut commands, &map.0, tile_size);
+        }
+
+        draw_state.set(DrawState::Update);
+    }
+
+    fn render_map(commands: &mut Commands, map: &map::Map, theme: &Theme, tile_size: TileSize) {
+        for (i, row) in map.tiles.iter().enumerate() {
+            for (j, tile) in row.iter().enumerate() {
+                render_tile(commands, j, i, tile, theme, tile_size);
+            }
+        }
+    }
+
+    // Outlines every tile that links to another map
+    fn render_connection_markers(
+        commands: &mut Commands,
+        map: &map::Map,
+        broken: &[(usize, usize)],
+        tile_size: TileSize,
+    ) {
+        let border = tile_size.width.min(tile_size.height) * CONNECTION_BORDER;
+        let horizontal_edge = (tile_size.height - border) * 0.5;
+        let vertical_edge = (tile_size.width - border) * 0.5;
+
+        for (i, row) in map.tiles.iter().enumerate() {
+            for (j, tile) in row.iter().enumerate() {
+                if tile.connection.is_none() {
+                    continue;
+                }
+
+                for (offset, size) in [
+                    (
+                        Vec2::new(0.0, horizontal_edge),
+                        Vec2::new(tile_size.width, border),
+                    ),
+                    (
+                        Vec2::new(0.0, -horizontal_edge),
+                        Vec2::new(tile_size.width, border),
+                    ),
+                    (
+                        Vec2::new(vertical_edge, 0.0),
+                        Vec2::new(border, tile_size.height),
+                    ),
+                    (
+                        Vec2::new(-vertical_edge, 0.0),
+                        Vec2::new(border, tile_size.height),
+                    ),
+                ] {
+                    commands.spawn((
+                        SpriteBundle {
+                            sprite: Sprite {
+                                color: if broken.contains(&(j, i)) {
+                                    BROKEN_CONNECTION_COLOR
+                                } else {
+                                    CONNECTION_COLOR
+                                },
+                                custom_size: Some(size),
+                                ..default()
+                            },
+                            transform: Transform::from_translation(
+                                (tile_size.tile_to_world(j as i32, i as i32) + offset).extend(2.0),
+                            ),
+                            ..default()
+                        },
+                        TileComponent,
+                        ConnectionMarker,
+                    ));
+                }
+            }
+        }
+    }
+
+    // Just the (x, y) of each tile, small in the bottom of the tile
+    fn render_coordinates(commands: &mut Commands, map: &map::Map, tile_size: TileSize) {
+        for (i, row) in map.tiles.iter().enumerate() {
+            for j in 0..row.len() {
+                commands.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(
+                            format!("({}, {})", j, i),
+                            TextStyle {
+                                font_size: 40.0,
+                                color: Color::DARK_GRAY,
+                                ..default()
+                            },
+                        ),
+                        transform: Transform::from_translation(
+                            (tile_size.tile_to_world(j as i32, i as i32)
+                                - Vec2::Y * tile_size.height * 0.4)
+                                .extend(2.0),
+                        ),
+                        ..default()
+                    },
+                    TileComponent,
+                ));
+            }
+        }
+    }
+
+    // World positions of the tile edges across `count` tiles of `size`, the first tile centered on 0
+    fn grid_lines(count: usize, size: f32) -> Vec<f32> {
+        (0..=count).map(|i| (i as f32 - 0.5) * size).collect()
+    }
+
+    fn render_grid(commands: &mut Commands, map: &map::Map, tile_size: TileSize) {
+        if map.is_empty() {
+            return;
+        }
+
+        let (width, height) = map.dimensions();
+        let thickness = tile_size.width.min(tile_size.height) * GRID_LINE_WIDTH;
+        let xs = grid_lines(width, tile_size.width);
+        let ys = grid_lines(height, tile_size.height);
+        let (left, right) = (xs[0], xs[width]);
+        let (bottom, top) = (ys[0], ys[height]);
+
+        let vertical = xs.iter().map(|&x| {
+            (
+                Vec2::new(x, (bottom + top) * 0.5),
+                Vec2::new(thickness, top - bottom),
+            )
+        });
+        let horizontal = ys.iter().map(|&y| {
+            (
+                Vec2::new((left + right) * 0.5, y),
+                Vec2::new(right - left, thickness),
+            )
+        });
+
+        for (center, size) in vertical.chain(horizontal) {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: GRID_COLOR,
+                        custom_size: Some(size),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(center.extend(1.8)),
+                    ..default()
+                },
+                TileComponent,
+                GridLine,
+            ));
+        }
+    }
+
+    fn render_tile(
+        commands: &mut Commands,
+        x: usize,
+        y: usize,
+        tile: &Tile,
+        theme: &Theme,
+        tile_size: TileSize,
+    ) {
+        commands
+            .spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: theme.tile_color(tile.tile_type),
+                        custom_size: Some(tile_size.as_vec2()),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(
+                        tile_size.tile_to_world(x as i32, y as i32).extend(0.0),
+                    ),
+                    ..default()
+                },
+                TileComponent,
+            ))
+            .with_children(|parent| {
+                if let Some(floor_object) = &tile.floor_object {
+                    parent.spawn(SpriteBundle {
+                        sprite: Sprite {
+                            color: theme.object_color(floor_object.object_type),
+                            custom_size: Some(tile_size.as_vec2() * FLOOR_OBJECT_SIZE),
+                            ..default()
+                        },
+                        transform: Transform::from_xyz(0.0, 0.0, 0.1),
+                        ..default()
+                    });
+                }
+                // decorations are stacked over the object in order
+                for (layer, object) in tile.objects().enumerate() {
+                    let mut sprite = object_sprite(object, theme, tile_size);
+                    sprite.transform.translation.z += layer as f32 * 0.01;
+                    parent.spawn(sprite);
+                }
+            });
+    }
+
+    // A bar along the edge the object faces, relative to its tile. Objects turned between two directions
+    // have no edge and sit in the middle as a square
+    fn object_sprite(object: &Object, theme: &Theme, tile_size: TileSize) -> SpriteBundle {
+        let size = tile_size.as_vec2();
+        let (custom_size, transform) = match Direction::from_quat(object.rotation) {
+            Some(facing) => {
+                let (dx, dy) = facing.offset();
+                let offset =
+                    Vec2::new(dx as f32, dy as f32) * size * (1.0 - OBJECT_THICKNESS) * 0.5;
+                // the bar is laid out for north and south and turned a quarter for east and west
+                let (length, rotation) = if dx == 0 {
+                    (size, Quat::IDENTITY)
+                } else {
+                    (Vec2::new(size.y, size.x), Quat::from_rotation_z(FRAC_PI_2))
+                };
+                (
+                    Vec2::new(length.x, length.y * OBJECT_THICKNESS),
+                    Transform::from_translation(offset.extend(0.2)).with_rotation(rotation),
+                )
+            }
+            None => (
+                size * OBJECT_THICKNESS * 2.0,
+                Transform::from_xyz(0.0, 0.0, 0.2),
+            ),
+        };
+
+        SpriteBundle {
+            sprite: Sprite {
+                color: theme.object_color(object.object_type),
+                custom_size: Some(custom_size),
+                ..default()
+            },
+            transform,
+            ..default()
+        }
+    }
+
+    // What the tooltip shows for a tile, one detail per line
+    fn tile_details(x: usize, y: usize, tile: &Tile) -> String {
+        let mut lines = vec![format!("({}, {}) {:?}", x, y, tile.tile_type)];
+
+        for object in tile.objects() {
+            lines.push(match Direction::from_quat(object.rotation) {
+                Some(facing) => format!("Object: {} facing {:?}", object.object_type, facing),
+                None => format!("Object: {}", object.object_type),
+            });
+        }
+        if let Some(floor_object) = &tile.floor_object {
+            lines.push(format!("Floor: {}", floor_object.object_type));
+        }
+        if let Some(connection) = &tile.connection {
+            lines.push(connection.to_string());
+        }
+
+        lines.join("\n")
+    }
+
+    fn update_tooltip(
+        map: Res<LiveMap>,
+        tile_size: Res<TileSize>,
+        windows: Query<&Window>,
+        camera: Query<&Transform, With<Camera>>,
+        mut tooltip: Query<(&mut Text, &mut Style, &mut Visibility), With<HoverTooltip>>,
+    ) {
+        let (Ok(window), Ok(camera)) = (windows.get_single(), camera.get_single()) else {
+            return;
+        };
+        let hovered = hovered_tile(window, camera, &map.0, *tile_size);
+
+        for (mut text, mut style, mut visibility) in &mut tooltip {
+            let (Some((x, y)), Some(cursor)) = (hovered, window.cursor_position()) else {
+                *visibility = Visibility::Hidden;
+                continue;
+            };
+
+            let details = tile_details(x, y, &map.0.tiles[y][x]);
+            if text.sections[0].value != details {
+                text.sections[0].value = details;
+            }
+            style.left = Val::Px(cursor.x + TOOLTIP_OFFSET.x);
+            style.top = Val::Px(cursor.y + TOOLTIP_OFFSET.y);
+            *visibility = Visibility::Inherited;
+        }
+    }
+
+    // Cursor position relative to the center of the window, with y pointing up like the world
+    fn cursor_offset(window: &Window) -> Option<Vec2> {
+        window.cursor_position().map(|position| {
+            Vec2::new(
+                position.x - window.width() * 0.5,
+                window.height() * 0.5 - position.y,
+            )
+        })
+    }
+
+    // The in-bounds tile under the cursor as (x, y)
+    fn hovered_tile(
+        window: &Window,
+        camera: &Transform,
+        map: &Map,
+        tile_size: TileSize,
+    ) -> Option<(usize, usize)> {
+        let (x, y) = tile_size.world_to_tile(cursor_world(window, camera)?);
+        let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+        map.get(x, y)?;
+        Some((x, y))
+    }
+
+    fn cursor_world(window: &Window, camera: &Transform) -> Option<Vec2> {
+        Some(camera.translation.truncate() + cursor_offset(window)? * camera.scale.truncate())
+    }
+
+    fn modifier_held(keyboard_input: &Input<KeyCode>) -> bool {
+        keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+    }
+
+    fn mouse_navigation(
+        mut camera: Query<&mut Transform, With<Camera>>,
+        mouse_input: Res<Input<MouseButton>>,
+        keyboard_input: Res<Input<KeyCode>>,
+        windows: Query<&Window>,
+        mut anchor: ResMut<PanAnchor>,
+        mut scroll: EventReader<MouseWheel>,
+        limits: Res<CameraLimits>,
+    ) {
+        if let Ok(mut camera) = camera.get_single_mut() {
+            if !mouse_input.pressed(MouseButton::Right) {
+                anchor.0 = None;
+            } else if let Some(offset) = windows.get_single().ok().and_then(cursor_offset) {
+                let scale = camera.scale.truncate();
+
+                match anchor.0 {
+                    // move the camera so the grabbed point stays under the cursor
+                    Some(grabbed) => {
+                        let translation = grabbed - offset * scale;
+                        camera.translation.x = translation.x;
+                        camera.translation.y = translation.y;
+                    }
+                    None => anchor.0 = Some(camera.translation.truncate() + offset * scale),
+                }
+            }
+
+            // the wheel cycles tile types instead while the modifier is held
+            if modifier_held(&keyboard_input) {
+                scroll.clear();
+                return;
+            }
+
+            for event in scroll.iter() {
+                camera.scale.x = limits.scrolled(camera.scale.x, event.y);
+                camera.scale.y = limits.scrolled(camera.scale.y, event.y);
+            }
+        }
+    }
+
+    // Ctrl + scroll steps the hovered tile through every tile type
+    fn cycle_hovered_tile(
+        camera: Query<&Transform, With<Camera>>,
+        windows: Query<&Window>,
+        keyboard_input: Res<Input<KeyCode>>,
+        mut scroll: EventReader<MouseWheel>,
+        mut map: ResMut<LiveMap>,
+        tile_size: Res<TileSize>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        let steps: f32 = scroll.iter().map(|event| event.y).sum();
+        if steps == 0.0 || !modifier_held(&keyboard_input) {
+            return;
+        }
+
+        let (Ok(camera), Ok(window)) = (camera.get_single(), windows.get_single()) else {
+            return;
+        };
+        let Some((x, y)) = hovered_tile(window, camera, &map.0, *tile_size) else {
+            return;
+        };
+
+        let tile = &mut map.0.tiles[y][x];
+        let count = TileType::ALL.len() as i32;
+        let current = TileType::ALL
+            .iter()
+            .position(|tile_type| *tile_type == tile.tile_type)
+            .unwrap_or(0) as i32;
+        let next = (current + steps.signum() as i32).rem_euclid(count);
+        tile.tile_type = TileType::ALL[next as usize];
+
+        draw_state.set(DrawState::Refresh);
+    }
+
+    fn shift_held(keyboard_input: &Input<KeyCode>) -> bool {
+        keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+    }
+
+    // Shift + left click selects the hovered tile, Delete clears the selected one
+    fn tile_selection(
+        camera: Query<&Transform, With<Camera>>,
+        windows: Query<&Window>,
+        mouse_input: Res<Input<MouseButton>>,
+        keyboard_input: Res<Input<KeyCode>>,
+        mut map: ResMut<LiveMap>,
+        mut selected: ResMut<SelectedTile>,
+        tile_size: Res<TileSize>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        if mouse_input.just_pressed(MouseButton::Left) && shift_held(&keyboard_input) {
+            if let (Ok(camera), Ok(window)) = (camera.get_single(), windows.get_single()) {
+                selected.0 = hovered_tile(window, camera, &map.0, *tile_size);
+                draw_state.set(DrawState::Refresh);
+            }
+        }
+
+        if EditorAction::ClearSelected.just_pressed(&keyboard_input) {
+            let Some((x, y)) = selected.0 else {
+                return;
+            };
+
+            if let Some(tile) = map.0.get_mut(x, y) {
+                tile.clear();
+                draw_state.set(DrawState::Refresh);
+            }
+        }
+    }
+
+    // Dragging paints every tile between the last frame's tile and this one so fast drags don't skip any,
+    // the path and link tools only take clicks
+    fn mouse_input(
+        camera: Query<&Transform, With<Camera>>,
+        tile_size: Res<TileSize>,
+        mouse_input: Res<Input<MouseButton>>,
+        keyboard_input: Res<Input<KeyCode>>,
+        windows: Query<&Window>,
+        tool: Res<Tool>,
+        palette: Query<&Interaction, With<ToolButton>>,
+        mut stroke: ResMut<PaintStroke>,
+        mut last: ResMut<LastPainted>,
+    ) {
+        // clicks on the palette don't reach the map
+        let over_palette = palette
+            .iter()
+            .any(|interaction| *interaction != Interaction::None);
+        if !mouse_input.pressed(MouseButton::Left) || shift_held(&keyboard_input) || over_palette {
+            last.0 = None;
+            return;
+        }
+        if tool.takes_clicks() && !mouse_input.just_pressed(MouseButton::Left) {
+            return;
+        }
+
+        let (Ok(camera), Ok(window)) = (camera.get_single(), windows.get_single()) else {
+            return;
+        };
+        let Some(world) = cursor_world(window, camera) else {
+            return;
+        };
+        let tile = tile_size.world_to_tile(world);
+
+        match last.0 {
+            Some(previous) if previous == tile => return,
+            Some(previous) => stroke
+                .0
+                .extend(line_points(previous, tile).into_iter().skip(1)),
+            None => stroke.0.push(tile),
+        }
+        last.0 = Some(tile);
+    }
+
+    // Arrow keys move the cursor and Space or Enter paints under it, the view follows the cursor
+    fn keyboard_cursor(
+        keyboard_input: Res<Input<KeyCode>>,
+        mut cursor: ResMut<CursorTile>,
+        mut stroke: ResMut<PaintStroke>,
+        tile_size: Res<TileSize>,
+        windows: Query<&Window>,
+        mut highlight: Query<(&mut Transform, &mut Sprite), With<CursorHighlight>>,
+        mut camera: Query<&mut Transform, (With<Camera>, Without<CursorHighlight>)>,
+    ) {
+        let (mut x, mut y) = cursor.0;
+        for (key, (dx, dy)) in [
+            (KeyCode::Up, (0, 1)),
+            (KeyCode::Down, (0, -1)),
+            (KeyCode::Left, (-1, 0)),
+            (KeyCode::Right, (1, 0)),
+        ] {
+            if keyboard_input.just_pressed(key) {
+                x += dx;
+                y += dy;
+            }
+        }
+        if cursor.0 != (x, y) {
+            cursor.0 = (x, y);
+        }
+
+        if keyboard_input.any_just_pressed([KeyCode::Space, KeyCode::Return]) {
+            stroke.0.push(cursor.0);
+        }
+
+        let center = tile_size.tile_to_world(x, y);
+        for (mut transform, mut sprite) in &mut highlight {
+            transform.translation = center.extend(1.6);
+            sprite.custom_size = Some(tile_size.as_vec2());
+        }
+
+        if !cursor.is_changed() {
+            return;
+        }
+
+        // scroll just enough to bring the cursor tile back on screen
+        if let (Ok(mut camera), Ok(window)) = (camera.get_single_mut(), windows.get_single()) {
+            let half_view =
+                Vec2::new(window.width(), window.height()) * 0.5 * camera.scale.truncate();
+            let reach = (half_view - tile_size.as_vec2() * 0.5).max(Vec2::ZERO);
+            let offset = center - camera.translation.truncate();
+            let correction = offset - offset.clamp(-reach, reach);
+            camera.translation.x += correction.x;
+            camera.translation.y += correction.y;
+        }
+    }
+
+    fn apply_tool(tool: Tool, facing: Direction, tile: &mut Tile) {
+        match tool {
+            Tool::Paint => tile.tile_type = TileType::Walkable,
+            Tool::Wall => {
+                tile.tile_type = TileType::Walkable;
+                tile.object = Some(Object::facing(ObjectType::Wall, facing));
+            }
+            Tool::Door => {
+                tile.tile_type = TileType::Walkable;
+                tile.object = Some(Object::facing(ObjectType::Door, facing));
+            }
+            Tool::Erase => *tile = Tile::default(),
+            Tool::Path | Tool::Link => {}
+        }
+    }
+
+    // With the path tool clicks pick the ends of the preview instead of painting,
+    // a click after both ends are set starts over
+    fn pick_path_ends(
+        tool: Res<Tool>,
+        map: Res<LiveMap>,
+        mut stroke: ResMut<PaintStroke>,
+        mut preview: ResMut<PathPreview>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        if *tool != Tool::Path || stroke.0.is_empty() {
+            return;
+        }
+
+        for (x, y) in stroke.0.drain(..) {
+            let (Ok(x), Ok(y)) = (usize::try_from(x), usize::try_from(y)) else {
+                continue;
+            };
+            if map.0.get(x, y).is_none() {
+                continue;
+            }
+
+            match preview.start {
+                Some(start) if preview.goal.is_none() => {
+                    preview.goal = Some((x, y));
+                    preview.path = map.0.find_path(start, (x, y));
+                }
+                _ => {
+                    *preview = PathPreview {
+                        start: Some((x, y)),
+                        ..default()
+                    };
+                }
+            }
+        }
+
+        draw_state.set(DrawState::Refresh);
+    }
+
+    // Paints everything queued this frame, growing the map once to fit the whole stroke
+    fn apply_paint_stroke(
+        mut stroke: ResMut<PaintStroke>,
+        mut map: ResMut<LiveMap>,
+        mut camera: Query<&mut Transform, With<Camera>>,
+        limits: Res<MapLimits>,
+        tool: Res<Tool>,
+        facing: Res<PlacementFacing>,
+        tile_size: Res<TileSize>,
+        mut cursor: ResMut<CursorTile>,
+        mut last: ResMut<LastPainted>,
+        mut errors: EventWriter<EditorError>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        if stroke.0.is_empty() {
+            return;
+        }
+        let painted = std::mem::take(&mut stroke.0);
+
+        let (min_x, min_y) = painted.iter().fold((0, 0), |(min_x, min_y), &(x, y)| {
+            (min_x.min(x), min_y.min(y))
+        });
+        let (max_x, max_y) = painted.iter().fold((0, 0), |(max_x, max_y), &(x, y)| {
+            (max_x.max(x), max_y.max(y))
+        });
+
+        // growing right and up first leaves existing indices alone
+        if let Err(error) = map.0.expand_to(max_x, max_y, limits.max_dimension) {
+            errors.send(EditorError(format!("Ignoring stroke: {}", error)));
+            return;
+        }
+        // how far growing left and down moved every tile
+        let (shift_x, shift_y) = match map.0.expand_to(min_x, min_y, limits.max_dimension) {
+            Ok((shift_x, shift_y)) => (shift_x as i32, shift_y as i32),
+            Err(error) => {
+                errors.send(EditorError(format!("Ignoring stroke: {}", error)));
+                return;
+            }
+        };
+
+        for (x, y) in painted {
+            let (Ok(x), Ok(y)) = (usize::try_from(x + shift_x), usize::try_from(y + shift_y))
+            else {
+                continue;
+            };
+            if let Some(tile) = map.0.get_mut(x, y) {
+                apply_tool(*tool, facing.0, tile);
+            }
+        }
+
+        // keep the view, the keyboard cursor and the drag on the same tiles after they moved
+        if let Ok(mut camera) = camera.get_single_mut() {
+            camera.translation.x += shift_x as f32 * tile_size.width;
+            camera.translation.y += shift_y as f32 * tile_size.height;
+        }
+        if (shift_x, shift_y) != (0, 0) {
+            let (x, y) = cursor.0;
+            cursor.0 = (x + shift_x, y + shift_y);
+            if let Some((x, y)) = last.0 {
+                last.0 = Some((x + shift_x, y + shift_y));
+            }
+        }
+
+        draw_state.set(DrawState::Refresh);
+    }
+
+    fn keybinds_closed(open: Res<KeybindsOpen>) -> bool {
+        !open.0
+    }
+
+    // Escape also closes the list since nothing else can be done while it is open
+    fn toggle_keybinds(
+        keyboard_input: Res<Input<KeyCode>>,
+        mut open: ResMut<KeybindsOpen>,
+        mut overlay: Query<&mut Visibility, With<KeybindOverlay>>,
+    ) {
+        if EditorAction::Keybinds.just_pressed(&keyboard_input) {
+            open.0 = !open.0;
+        } else if open.0 && keyboard_input.just_pressed(KeyCode::Escape) {
+            open.0 = false;
+        } else {
+            return;
+        }
+
+        for mut visibility in &mut overlay {
+            *visibility = if open.0 {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+        }
+    }
+
+    fn tool_select(
+        mut tool: ResMut<Tool>,
+        keyboard_input: Res<Input<KeyCode>>,
+        buttons: Query<(&Interaction, &ToolButton), Changed<Interaction>>,
+        mut facing: ResMut<PlacementFacing>,
+        mut preview: ResMut<PathPreview>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        let previous = *tool;
+
+        if EditorAction::CycleTool.just_pressed(&keyboard_input) {
+            *tool = tool.next();
+        }
+
+        for (key, selected) in [
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Key4,
+            KeyCode::Key5,
+            KeyCode::Key6,
+        ]
+        .into_iter()
+        .zip(Tool::ALL)
+        {
+            if keyboard_input.just_pressed(key) {
+                *tool = selected;
+            }
+        }
+
+        for (interaction, button) in &buttons {
+            if *interaction == Interaction::Pressed {
+                *tool = button.0;
+            }
+        }
+
+        if EditorAction::RotateObject.just_pressed(&keyboard_input) {
+            facing.0 = facing.0.clockwise();
+        }
+
+        // the path overlay only lives while the path tool is held
+        if previous == Tool::Path && *tool != Tool::Path {
+            *preview = PathPreview::default();
+            draw_state.set(DrawState::Refresh);
+        }
+    }
+
+    fn update_tool_indicator(
+        tool: Res<Tool>,
+        facing: Res<PlacementFacing>,
+        preview: Res<PathPreview>,
+        mut indicator: Query<&mut Text, With<ToolIndicator>>,
+        mut buttons: Query<(&Interaction, &ToolButton, &mut BackgroundColor)>,
+    ) {
+        for (interaction, button, mut color) in &mut buttons {
+            *color = match interaction {
+                _ if button.0 == *tool => PALETTE_SELECTED_COLOR.into(),
+                Interaction::Hovered | Interaction::Pressed => PALETTE_HOVERED_COLOR.into(),
+                Interaction::None => PALETTE_COLOR.into(),
+            };
+        }
+
+        if tool.is_changed() || facing.is_changed() || preview.is_changed() {
+            for mut text in indicator.iter_mut() {
+                text.sections[0].value = match *tool {
+                    Tool::Path => format!("Tool: {} ({})", tool.name(), preview.status()),
+                    _ if tool.places_object() => {
+                        format!("Tool: {} (facing {:?})", tool.name(), facing.0)
+                    }
+                    _ => format!("Tool: {}", tool.name()),
+                };
+            }
+        }
+    }
+
+    // Connection targets are named relative to the open file's directory
+    fn connection_directory(current_file: &CurrentFile) -> PathBuf {
+        current_file
+            .path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .map(|directory| directory.to_path_buf())
+            .unwrap_or_default()
+    }
+
+    // Saving goes straight back to the open file, the dialog is only for a new file or save as
+    fn quick_save_path(current_file: &CurrentFile, save_as: bool) -> Option<PathBuf> {
+        current_file.path.clone().filter(|_| !save_as)
+    }
+
+    // A connection to the map at `target`, named relative to `directory` when it's inside it
+    fn connection_to(directory: &Path, target: &Path, spawn: (usize, usize)) -> Connection {
+        Connection {
+            map: target
+                .strip_prefix(directory)
+                .unwrap_or(target)
+                .to_string_lossy()
+                .into_owned(),
+            spawn,
+            floor: None,
+        }
+    }
+
+    // L links the selected tile to a map picked with the file dialog, a click with the link tool selects
+    // the tile and does the same. The spawn is typed into the link prompt, starting from the map's own spawn
+    fn link_selected_tile(
+        keyboard_input: Res<Input<KeyCode>>,
+        tool: Res<Tool>,
+        mut stroke: ResMut<PaintStroke>,
+        mut selected: ResMut<SelectedTile>,
+        current_file: Res<CurrentFile>,
+        map: Res<LiveMap>,
+        mut pending: ResMut<PendingLink>,
+        mut errors: EventWriter<EditorError>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        let clicked = if *tool == Tool::Link {
+            std::mem::take(&mut stroke.0)
+                .into_iter()
+                .rev()
+                .find_map(|(x, y)| {
+                    let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+                    map.0.get(x, y).map(|_| (x, y))
+                })
+        } else {
+            None
+        };
+        if clicked.is_some() {
+            selected.0 = clicked;
+            draw_state.set(DrawState::Refresh);
+        } else if !EditorAction::LinkSelected.just_pressed(&keyboard_input) {
+            return;
+        }
+        let Some((x, y)) = selected.0.filter(|&(x, y)| map.0.get(x, y).is_some()) else {
+            errors.send(EditorError("Select a tile to link first".to_string()));
+            return;
+        };
+
+        let directory = connection_directory(&current_file);
+        let mut dialog = FileDialog::new().add_filter("Map", &["map", "mapb"]);
+        if current_file.path.is_some() {
+            dialog = dialog.set_directory(&directory);
+        }
+        let Some(path) = dialog.pick_file() else {
+            return;
+        };
+
+        let target = match Map::load_from_path(&path) {
+            Ok(target) => target,
+            Err(error) => {
+                errors.send(EditorError::file("load", &path, error));
+                return;
+            }
+        };
+        let Some((spawn_x, spawn_y)) = target.default_spawn() else {
+            errors.send(EditorError(format!(
+                "{} has no tile to spawn on",
+                path.display()
+            )));
+            return;
+        };
+
+        pending.0 = Some(LinkEntry {
+            at: (x, y),
+            path,
+            target,
+            spawn: format!("{}, {}", spawn_x, spawn_y),
+        });
+    }
+
+    fn no_pending_link(pending: Res<PendingLink>) -> bool {
+        pending.0.is_none()
+    }
+
+    // "x, y" as typed into the link prompt, the comma is optional
+    fn parse_spawn(typed: &str) -> Option<(usize, usize)> {
+        let numbers = typed
+            .split([',', ' '])
+            .filter(|number| !number.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<usize>, _>>()
+            .ok()?;
+
+        match numbers[..] {
+            [x, y] => Some((x, y)),
+            _ => None,
+        }
+    }
+
+    // Puts `connection` on the tile at `at` if its spawn validates against `target`, otherwise the tile keeps
+    // the connection it had
+    fn link_tile(
+        map: &mut Map,
+        at: (usize, usize),
+        connection: Connection,
+        target: &Map,
+    ) -> Result<(), Vec<ConnectionError>> {
+        let (x, y) = at;
+        let name = connection.map.clone();
+        let previous = map.tiles[y][x].connection.replace(connection);
+        let broken: Vec<_> = map
+            .validate_connections(|requested| (requested == name).then(|| target.clone()))
+            .into_iter()
+            .filter(|error| error.at() == at)
+            .collect();
+
+        if broken.is_empty() {
+            Ok(())
+        } else {
+            map.tiles[y][x].connection = previous;
+            Err(broken)
+        }
+    }
+
+    // Digits, commas and spaces edit the pending link's spawn and Backspace removes the last one.
+    // Enter links the tile once the spawn validates, Escape drops the link
+    fn link_entry(
+        mut pending: ResMut<PendingLink>,
+        mut typed: EventReader<ReceivedCharacter>,
+        keyboard_input: Res<Input<KeyCode>>,
+        current_file: Res<CurrentFile>,
+        mut map: ResMut<LiveMap>,
+        mut broken: ResMut<BrokenConnections>,
+        mut errors: EventWriter<EditorError>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        let Some(entry) = pending.0.as_mut() else {
+            return;
+        };
+
+        for character in typed.iter().map(|event| event.char) {
+            if character.is_ascii_digit() || character == ',' || character == ' ' {
+                entry.spawn.push(character);
+            }
+        }
+        if keyboard_input.just_pressed(KeyCode::Back) {
+            entry.spawn.pop();
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Escape) {
+            pending.0 = None;
+            return;
+        }
+        if !keyboard_input.just_pressed(KeyCode::Return) {
+            return;
+        }
+
+        let Some(spawn) = parse_spawn(&entry.spawn) else {
+            errors.send(EditorError("Type the spawn as x, y".to_string()));
+            return;
+        };
+        let connection = connection_to(&connection_directory(&current_file), &entry.path, spawn);
+        let at = entry.at;
+
+        match link_tile(&mut map.0, at, connection, &entry.target) {
+            Ok(()) => {
+                pending.0 = None;
+                broken.0.retain(|&broken_at| broken_at != at);
+                draw_state.set(DrawState::Refresh);
+            }
+            Err(broken_link) => errors.send_batch(
+                broken_link
+                    .iter()
+                    .map(|error| EditorError(error.to_string())),
+            ),
+        }
+    }
+
+    fn update_link_prompt(
+        pending: Res<PendingLink>,
+        mut prompt: Query<(&mut Text, &mut Visibility), With<LinkPrompt>>,
+    ) {
+        if !pending.is_changed() {
+            return;
+        }
+
+        for (mut text, mut visibility) in &mut prompt {
+            let Some(entry) = &pending.0 else {
+                *visibility = Visibility::Hidden;
+                continue;
+            };
+
+            text.sections[0].value = format!(
+                "Link ({}, {}) to {}\nArrive at: {}_\n\nEnter links, Escape cancels",
+                entry.at.0,
+                entry.at.1,
+                entry.path.display(),
+                entry.spawn
+            );
+            *visibility = Visibility::Inherited;
+        }
+    }
+
+    fn keyboard_input(
+        mut map: ResMut<LiveMap>,
+        mut current_file: ResMut<CurrentFile>,
+        keyboard_input: Res<Input<KeyCode>>,
+        mut overlay: ResMut<ConnectionOverlay>,
+        mut coordinates: ResMut<CoordinateOverlay>,
+        mut grid: ResMut<GridOverlay>,
+        mut broken: ResMut<BrokenConnections>,
+        theme: Res<Theme>,
+        mut errors: EventWriter<EditorError>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+        mut mode: ResMut<NextState<EditorMode>>,
+    ) {
+        if EditorAction::ValidateConnections.just_pressed(&keyboard_input) {
+            let directory = connection_directory(&current_file);
+            let errors = map
+                .0
+                .validate_connections(|name| Map::load_from_path(directory.join(name)).ok());
+
+            let total = map
+                .0
+                .tiles
+                .iter()
+                .flatten()
+                .filter(|tile| tile.connection.is_some())
+                .count();
+            println!("Checked {} connections, {} broken", total, errors.len());
+            for error in &errors {
+                println!("{}", error);
+            }
+
+            broken.0 = errors.iter().map(|error| error.at()).collect();
+            overlay.0 = true;
+            draw_state.set(DrawState::Refresh);
+        }
+
+        if EditorAction::Playtest.just_pressed(&keyboard_input) {
+            mode.set(EditorMode::Play);
+        }
+
+        if EditorAction::ToggleConnections.just_pressed(&keyboard_input) {
+            overlay.0 = !overlay.0;
+            draw_state.set(DrawState::Refresh);
+        }
+
+        if EditorAction::ToggleCoordinates.just_pressed(&keyboard_input) {
+            coordinates.0 = !coordinates.0;
+            draw_state.set(DrawState::Refresh);
+        }
+
+        if EditorAction::ToggleGrid.just_pressed(&keyboard_input) {
+            grid.0 = !grid.0;
+            draw_state.set(DrawState::Refresh);
+        }
+
+        if EditorAction::ExportObj.just_pressed(&keyboard_input) {
+            let file_dialog = FileDialog::new().add_filter("OBJ", &["obj"]).save_file();
+
+            if let Some(path) = file_dialog {
+                if let Err(error) = std::fs::write(path, map.0.to_obj()) {
+                    errors.send(EditorError(format!("Failed to export OBJ: {}", error)));
+                }
+            }
+        }
+
+        if EditorAction::ExportPng.just_pressed(&keyboard_input) {
+            let file_dialog = FileDialog::new().add_filter("PNG", &["png"]).save_file();
+
+            if let Some(path) = file_dialog {
+                if let Err(error) = map.0.as_image_buffer(PNG_BLOCK_SIZE, &theme).save(path) {
+                    errors.send(EditorError(format!("Failed to export PNG: {}", error)));
+                }
+            }
+        }
+
+        if EditorAction::BorderWalls.just_pressed(&keyboard_input) {
+            map.0.add_border_walls();
+            draw_state.set(DrawState::Refresh);
+        }
+
+        let save_as = EditorAction::SaveAs.just_pressed(&keyboard_input);
+        if save_as || EditorAction::Save.just_pressed(&keyboard_input) {
+            if map.0.is_empty() {
+                errors.send(EditorError("Refusing to save an empty map".to_string()));
+                return;
+            }
+
+            // the extension picks json or binary
+            let target = match quick_save_path(&current_file, save_as) {
+                Some(path) => Some(path),
+                None => FileDialog::new()
+                    .add_filter("Map", &["map"])
+                    .add_filter("Binary map", &["mapb"])
+                    .save_file(),
+            };
+
+            if let Some(path) = target {
+                let mut saved = map.0.clone();
+                saved.trim();
+                saved.pad(1);
+
+                match saved.save_to_path(&path) {
+                    Ok(()) => {
+                        *current_file = CurrentFile {
+                            path: Some(path),
+                            saved_hash: map.0.content_hash(),
+                        }
+                    }
+                    Err(error) => errors.send(EditorError::file("save", &path, error)),
+                }
+            }
+        }
+    }
+
+    // Ctrl + Z and Ctrl + Y step through the history. Edits are recorded once the mouse is released
+    // so a whole drag is a single step
+    fn edit_history(
+        keyboard_input: Res<Input<KeyCode>>,
+        mouse_input: Res<Input<MouseButton>>,
+        mut map: ResMut<LiveMap>,
+        mut history: ResMut<EditHistory>,
+        mut pending: Local<bool>,
+        mut draw_state: ResMut<NextState<DrawState>>,
+    ) {
+        let step = if EditorAction::Undo.just_pressed(&keyboard_input) {
+            history.undo().cloned()
+        } else if EditorAction::Redo.just_pressed(&keyboard_input) {
+            history.redo().cloned()
+        } else {
+            None
+        };
+        if let Some(step) = step {
+            map.0 = step;
+            *pending = false;
+            draw_state.set(DrawState::Refresh);
+            return;
+        }
+
+        if map.is_changed() {
+            *pending = true;
+        }
+        if *pending && !mouse_input.pressed(MouseButton::Left) {
+            history.record(&map.0);
+            *pending = false;
+        }
+    }
+
+    fn error_banner_setup(mut commands: Commands) {
+        commands.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                ..TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 24.0,
+                        color: ERROR_COLOR,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(10.0),
+                    right: Val::Px(10.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                })
+                .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.75))
+            },
+            ErrorBanner,
+        ));
+    }
+
+    // The newest error stays on screen for ERROR_SECONDS, every error is also logged
+    fn show_errors(
+        mut errors: EventReader<EditorError>,
+        mut banner: Query<(&mut Text, &mut Visibility), With<ErrorBanner>>,
+        mut shown_at: Local<Option<f32>>,
+        time: Res<Time>,
+    ) {
+        let now = time.elapsed_seconds();
+        let newest = errors
+            .iter()
+            .inspect(|error| println!("{}", error.0))
+            .last();
+
+        for (mut text, mut visibility) in &mut banner {
+            if let Some(error) = newest {
+                text.sections[0].value = error.0.clone();
+                *visibility = Visibility::Inherited;
+                *shown_at = Some(now);
+            } else if shown_at.is_some_and(|shown_at| now - shown_at > ERROR_SECONDS) {
+                *visibility = Visibility::Hidden;
+                *shown_at = None;
+            }
+        }
+    }
+
+    fn playtest_setup(
+        mut commands: Commands,
+        map: Res<LiveMap>,
+        mut position: ResMut<PlaytestPosition>,
+        mut camera: Query<&mut Transform, With<Camera>>,
+        mut mode: ResMut<NextState<EditorMode>>,
+        mut errors: EventWriter<EditorError>,
+        tile_size: Res<TileSize>,
+    ) {
+        let spawn = map.0.tiles.iter().enumerate().find_map(|(y, row)| {
+            (0..row.len())
+                .find(|&x| map.0.is_walkable(x, y))
+                .map(|x| (x, y))
+        });
+        let Some((x, y)) = spawn else {
+            errors.send(EditorError(
+                "Nothing to playtest, the map has no walkable tiles".to_string(),
+            ));
+            mode.set(EditorMode::Edit);
+            return;
+        };
+
+        position.0 = (x, y);
+        let translation = tile_size.tile_to_world(x as i32, y as i32).extend(2.0);
+
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::ORANGE,
+                    custom_size: Some(tile_size.as_vec2() * 0.6),
+                    ..default()
+                },
+                transform: Transform::from_translation(translation),
+                ..default()
+            },
+            PlaytestPlayer,
+        ));
+
+        if let Ok(mut camera) = camera.get_single_mut() {
+            camera.translation.x = translation.x;
+            camera.translation.y = translation.y;
+        }
+    }
+
+    // Steps around the live map with the same walkability rules as the game, Esc goes back to editing
+    fn playtest_input(
+        keyboard_input: Res<Input<KeyCode>>,
+        map: Res<LiveMap>,
+        mut position: ResMut<PlaytestPosition>,
+        mut player: Query<&mut Transform, With<PlaytestPlayer>>,
+        mut mode: ResMut<NextState<EditorMode>>,
+        tile_size: Res<TileSize>,
+    ) {
+        if keyboard_input.just_pressed(KeyCode::Escape) {
+            mode.set(EditorMode::Edit);
+            return;
+        }
+
+        // rows grow upwards on screen in the editor, so up is South in map terms
+        let Some(direction) = [
+            (KeyCode::W, Direction::South),
+            (KeyCode::S, Direction::North),
+            (KeyCode::A, Direction::West),
+            (KeyCode::D, Direction::East),
+        ]
+        .into_iter()
+        .find(|(key, _)| keyboard_input.just_pressed(*key))
+        .map(|(_, direction)| direction) else {
+            return;
+        };
+
+        let (x, y) = position.0;
+        let Some(((x, y), _)) = map
+            .0
+            .neighbor(x, y, direction)
+            .filter(|((x, y), _)| map.0.is_walkable(*x, *y))
+        else {
+            return;
+        };
+
+        position.0 = (x, y);
+        for mut transform in &mut player {
+            let translation = tile_size.tile_to_world(x as i32, y as i32);
+            transform.translation.x = translation.x;
+            transform.translation.y = translation.y;
+        }
+    }
+
+    // Shows the open file in the window title, with a * when there are unsaved changes
+    fn update_title(
+        map: Res<LiveMap>,
+        current_file: Res<CurrentFile>,
+        mut windows: Query<&mut Window>,
+    ) {
+        if !map.is_changed() && !current_file.is_changed() {
+            return;
+        }
+
+        let name = current_file
+            .path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Untitled".to_string());
+        let dirty = if map.0.content_hash() != current_file.saved_hash {
+            "*"
+        } else {
+            ""
+        };
+
+        for mut window in windows.iter_mut() {
+            window.title = format!("{} — {}{}", TITLE, name, dirty);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::time::Duration;
+
+        #[test]
+        fn file_errors_name_the_file() {
+            let path = Path::new("missing/level.map");
+            let EditorError(message) =
+                EditorError::file("load", path, Map::load_from_path(path).unwrap_err());
+
+            assert!(
+                message.starts_with("Failed to load missing/level.map: Failed to read map: "),
+                "{}",
+                message
+            );
+        }
+
+        #[test]
+        fn banner_shows_the_newest_error_until_it_times_out() {
+            let time = Time::default();
+            let start = time.startup();
+            let mut app = App::new();
+            app.insert_resource(time)
+                .add_event::<EditorError>()
+                .add_systems(Startup, error_banner_setup)
+                .add_systems(Update, show_errors);
+            app.update();
+
+            let mut banner = app
+                .world
+                .query_filtered::<(&Text, &Visibility), With<ErrorBanner>>();
+            assert_eq!(*banner.single(&app.world).1, Visibility::Hidden);
+
+            let mut events = app.world.resource_mut::<Events<EditorError>>();
+            events.send(EditorError("first".to_string()));
+            events.send(EditorError("second".to_string()));
+            app.update();
+
+            let (text, visibility) = banner.single(&app.world);
+            assert_eq!(text.sections[0].value, "second");
+            assert_eq!(*visibility, Visibility::Inherited);
+
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + Duration::from_secs_f32(ERROR_SECONDS + 1.0));
+            app.update();
+
+            assert_eq!(*banner.single(&app.world).1, Visibility::Hidden);
+        }
+
+        #[test]
+        fn s_saves_to_the_open_file_and_shift_s_asks() {
+            let untitled = CurrentFile::default();
+            assert_eq!(quick_save_path(&untitled, false), None);
+            assert_eq!(quick_save_path(&untitled, true), None);
+
+            let open = CurrentFile {
+                path: Some(PathBuf::from("levels/hall.map")),
+                saved_hash: 0,
+            };
+            assert_eq!(
+                quick_save_path(&open, false),
+                Some(PathBuf::from("levels/hall.map"))
+            );
+            assert_eq!(quick_save_path(&open, true), None);
+
+            let mut input = Input::<KeyCode>::default();
+            input.press(KeyCode::S);
+            assert!(EditorAction::Save.just_pressed(&input));
+            assert!(!EditorAction::SaveAs.just_pressed(&input));
+
+            input.press(KeyCode::ShiftLeft);
+            assert!(!EditorAction::Save.just_pressed(&input));
+            assert!(EditorAction::SaveAs.just_pressed(&input));
+        }
+
+        #[test]
+        fn history_undoes_and_redoes_in_order() {
+            let maps: Vec<Map> = (1..=3).map(|width| Map::new_blocked(width, 1)).collect();
+            let mut history = EditHistory::default();
+            history.reset(&maps[0]);
+
+            history.record(&maps[1]);
+            // recording the same map again isn't a step
+            history.record(&maps[1]);
+            history.record(&maps[2]);
+
+            assert_eq!(history.undo(), Some(&maps[1]));
+            assert_eq!(history.undo(), Some(&maps[0]));
+            assert_eq!(history.undo(), None);
+            assert_eq!(history.redo(), Some(&maps[1]));
+
+            // a new edit drops what was undone
+            history.record(&maps[0]);
+            assert_eq!(history.redo(), None);
+            assert_eq!(history.undo(), Some(&maps[1]));
+        }
+
+        #[test]
+        fn history_keeps_only_the_newest_steps() {
+            let mut history = EditHistory::default();
+            history.reset(&Map::default());
+            for width in 1..=MAX_HISTORY + 5 {
+                history.record(&Map::new_blocked(width, 1));
+            }
+
+            let mut undone = 0;
+            while history.undo().is_some() {
+                undone += 1;
+            }
+            assert_eq!(undone, MAX_HISTORY);
+            assert_eq!(history.current, Map::new_blocked(5, 1));
+        }
+
+        #[test]
+        fn tools_cycle_and_erase_clears_the_tile() {
+            let mut tool = Tool::default();
+            let mut cycled = Vec::new();
+            for _ in 0..Tool::ALL.len() {
+                cycled.push(tool);
+                tool = tool.next();
+            }
+            assert_eq!(cycled, Tool::ALL);
+            assert_eq!(tool, Tool::Paint);
+
+            let mut tile = Tile::walkable()
+                .with_object(ObjectType::Door, Quat::IDENTITY)
+                .with_connection("cellar.map", (1, 1));
+            apply_tool(Tool::Erase, Direction::North, &mut tile);
+            assert_eq!(tile, Tile::default());
+
+            apply_tool(Tool::Paint, Direction::North, &mut tile);
+            assert_eq!(tile, Tile::walkable());
+
+            // linking happens through the file dialog, the tool itself leaves the tile alone
+            apply_tool(Tool::Link, Direction::North, &mut tile);
+            assert_eq!(tile, Tile::walkable());
+        }
+
+        // tool_select alone, for pressing tool and rotation keys
+        fn tool_app() -> App {
+            let mut app = App::new();
+            app.init_resource::<Input<KeyCode>>()
+                .init_resource::<Tool>()
+                .init_resource::<PlacementFacing>()
+                .init_resource::<PathPreview>()
+                .init_resource::<NextState<DrawState>>()
+                .add_systems(Update, tool_select);
+            app
+        }
+
+        // Presses `key` for one update
+        fn tap(app: &mut App, key: KeyCode) {
+            let mut input = app.world.resource_mut::<Input<KeyCode>>();
+            input.reset_all();
+            input.press(key);
+            app.update();
+        }
+
+        #[test]
+        fn number_keys_pick_tools_and_walls_take_the_facing() {
+            let mut app = tool_app();
+
+            tap(&mut app, KeyCode::Key3);
+            assert_eq!(*app.world.resource::<Tool>(), Tool::Erase);
+            tap(&mut app, KeyCode::Tab);
+            assert_eq!(*app.world.resource::<Tool>(), Tool::Path);
+            tap(&mut app, KeyCode::Key2);
+            assert_eq!(*app.world.resource::<Tool>(), Tool::Wall);
+            tap(&mut app, KeyCode::Key6);
+            assert_eq!(*app.world.resource::<Tool>(), Tool::Link);
+
+            let mut tile = Tile::blocked();
+            apply_tool(Tool::Wall, Direction::East, &mut tile);
+            assert_eq!(tile.tile_type, TileType::Walkable);
+            assert_eq!(
+                tile.object,
+                Some(Object::facing(ObjectType::Wall, Direction::East))
+            );
+        }
+
+        #[test]
+        fn r_turns_placed_objects_clockwise() {
+            let mut app = tool_app();
+            let mut turns = Vec::new();
+            for _ in 0..4 {
+                tap(&mut app, KeyCode::R);
+                turns.push(app.world.resource::<PlacementFacing>().0);
+            }
+
+            assert_eq!(
+                turns,
+                [
+                    Direction::East,
+                    Direction::South,
+                    Direction::West,
+                    Direction::North,
+                ]
+            );
+        }
+
+        #[test]
+        fn placed_objects_survive_saving() {
+            let mut map = Map::new_blocked(2, 1);
+            apply_tool(Tool::Door, Direction::West, &mut map.tiles[0][0]);
+            apply_tool(Tool::Wall, Direction::South, &mut map.tiles[0][1]);
+
+            let directory =
+                std::env::temp_dir().join(format!("editor-objects-{}", std::process::id()));
+            std::fs::create_dir_all(&directory).unwrap();
+            let path = directory.join("objects.map");
+            map.save_to_path(&path).unwrap();
+            let loaded = Map::load_from_path(&path);
+            std::fs::remove_dir_all(&directory).unwrap();
+
+            assert_eq!(loaded.unwrap(), map);
+        }
+
+        #[test]
+        fn drags_fill_the_tiles_between_frames() {
+            let mut app = App::new();
+            app.init_resource::<Input<MouseButton>>()
+                .init_resource::<Input<KeyCode>>()
+                .init_resource::<Tool>()
+                .init_resource::<PaintStroke>()
+                .init_resource::<LastPainted>()
+                .insert_resource(TileSize::square(32.0))
+                .add_systems(Update, mouse_input);
+            app.world.spawn((Transform::default(), Camera::default()));
+            let window = app.world.spawn(Window::default()).id();
+            let move_cursor = |app: &mut App, offset: Vec2| {
+                let mut window = app.world.get_mut::<Window>(window).unwrap();
+                let center = Vec2::new(window.width(), window.height()) * 0.5;
+                window.set_cursor_position(Some(center + offset));
+            };
+
+            move_cursor(&mut app, Vec2::ZERO);
+            app.world
+                .resource_mut::<Input<MouseButton>>()
+                .press(MouseButton::Left);
+            app.update();
+            assert_eq!(app.world.resource::<PaintStroke>().0, [(0, 0)]);
+
+            // a fast drag three tiles over in one frame still paints the tiles it skipped
+            move_cursor(&mut app, Vec2::new(96.0, 0.0));
+            app.update();
+            assert_eq!(
+                app.world.resource::<PaintStroke>().0,
+                [(0, 0), (1, 0), (2, 0), (3, 0)]
+            );
+            assert_eq!(app.world.resource::<LastPainted>().0, Some((3, 0)));
+
+            app.world.resource_mut::<Input<MouseButton>>().release_all();
+            app.update();
+            assert_eq!(app.world.resource::<LastPainted>().0, None);
+        }
+
+        #[test]
+        fn connections_name_their_map_relative_to_the_open_file() {
+            let current_file = CurrentFile {
+                path: Some(PathBuf::from("levels/castle/hall.map")),
+                saved_hash: 0,
+            };
+            let directory = connection_directory(&current_file);
+            assert_eq!(directory, Path::new("levels/castle"));
+
+            assert_eq!(
+                connection_to(
+                    &directory,
+                    Path::new("levels/castle/cellar/wine.map"),
+                    (2, 3)
+                ),
+                Connection {
+                    map: "cellar/wine.map".to_string(),
+                    spawn: (2, 3),
+                    floor: None,
+                }
+            );
+            // maps outside the directory keep their whole path
+            assert_eq!(
+                connection_to(&directory, Path::new("other/tower.map"), (0, 0)).map,
+                "other/tower.map"
+            );
+
+            assert_eq!(
+                connection_directory(&CurrentFile::default()),
+                PathBuf::new()
+            );
+        }
+
+        #[test]
+        fn typed_spawns_need_two_numbers() {
+            assert_eq!(parse_spawn("3, 4"), Some((3, 4)));
+            assert_eq!(parse_spawn("3 4"), Some((3, 4)));
+            assert_eq!(parse_spawn(" 12,0 "), Some((12, 0)));
+            assert_eq!(parse_spawn("3"), None);
+            assert_eq!(parse_spawn("3, 4, 5"), None);
+            assert_eq!(parse_spawn(""), None);
+        }
+
+        // link_entry alone with a link from (1, 0) in levels/hall.map to levels/cellar.map waiting on `spawn`
+        fn link_app(spawn: &str) -> App {
+            let mut app = App::new();
+            app.init_resource::<Input<KeyCode>>()
+                .add_event::<ReceivedCharacter>()
+                .add_event::<EditorError>()
+                .init_resource::<BrokenConnections>()
+                .init_resource::<NextState<DrawState>>()
+                .insert_resource(CurrentFile {
+                    path: Some(PathBuf::from("levels/hall.map")),
+                    saved_hash: 0,
+                })
+                .insert_resource(LiveMap(Map::from_ascii("..\n..\n").unwrap()))
+                .insert_resource(PendingLink(Some(LinkEntry {
+                    at: (1, 0),
+                    path: PathBuf::from("levels/cellar.map"),
+                    target: Map::from_ascii("#.\n..\n").unwrap(),
+                    spawn: spawn.to_string(),
+                })))
+                .add_systems(Update, link_entry);
+            app
+        }
+
+        fn type_text(app: &mut App, typed: &str) {
+            for char in typed.chars() {
+                app.world.send_event(ReceivedCharacter {
+                    window: Entity::PLACEHOLDER,
+                    char,
+                });
+            }
+        }
+
+        #[test]
+        fn typed_spawns_are_validated_before_linking() {
+            let mut app = link_app("1, 1");
+            for _ in 0.."1, 1".len() {
+                tap(&mut app, KeyCode::Back);
+            }
+
+            // (0, 0) is blocked on the target, so the link stays open and the tile untouched
+            type_text(&mut app, "0, 0x");
+            tap(&mut app, KeyCode::Return);
+            assert!(!app.world.resource::<Events<EditorError>>().is_empty());
+            assert_eq!(
+                app.world.resource::<LiveMap>().0.tiles[0][1].connection,
+                None
+            );
+            let entry = app.world.resource::<PendingLink>();
+            assert_eq!(entry.0.as_ref().unwrap().spawn, "0, 0");
+
+            tap(&mut app, KeyCode::Back);
+            type_text(&mut app, "1");
+            tap(&mut app, KeyCode::Return);
+            assert!(app.world.resource::<PendingLink>().0.is_none());
+            assert_eq!(
+                app.world.resource::<LiveMap>().0.tiles[0][1].connection,
+                Some(Connection {
+                    map: "cellar.map".to_string(),
+                    spawn: (0, 1),
+                    floor: None,
+                })
+            );
+        }
+
+        #[test]
+        fn escape_drops_the_pending_link() {
+            let mut app = link_app("1, 1");
+            tap(&mut app, KeyCode::Escape);
+
+            assert!(app.world.resource::<PendingLink>().0.is_none());
+            assert_eq!(
+                app.world.resource::<LiveMap>().0,
+                Map::from_ascii("..\n..\n").unwrap()
+            );
+        }
+
+        #[test]
+        fn grid_lines_sit_on_tile_edges() {
+            assert_eq!(grid_lines(3, 32.0), [-16.0, 16.0, 48.0, 80.0]);
+            assert_eq!(grid_lines(0, 32.0), [-16.0]);
+        }
+
+        #[test]
+        fn zoom_stays_within_the_limits() {
+            let limits = CameraLimits::default();
+            assert_eq!(limits.clamp(1.0), 1.0);
+            assert_eq!(limits.clamp(0.0), limits.min_zoom);
+            assert_eq!(limits.clamp(1000.0), limits.max_zoom);
+
+            assert!((limits.scrolled(2.0, 1.0) - 2.2).abs() < 1e-6);
+            assert!((limits.scrolled(2.0, -1.0) - 1.8).abs() < 1e-6);
+
+            // a big enough scroll would flip the scale negative
+            assert_eq!(limits.scrolled(2.0, -20.0), limits.min_zoom);
+            assert_eq!(limits.scrolled(2.0, -30.0), limits.min_zoom);
+            assert_eq!(limits.scrolled(40.0, 10.0), limits.max_zoom);
+        }
+
+        #[test]
+        fn tooltip_describes_the_hovered_tile() {
+            let mut map = Map::from_ascii("..\n..\n").unwrap();
+            map.tiles[1][1] = Tile::walkable()
+                .with_object(ObjectType::Door, Direction::East.to_quat())
+                .with_floor_object(ObjectType::Wall)
+                .with_connection("cellar.map", (2, 3));
+            let tile_size = TileSize::square(32.0);
+            let camera = Transform::default();
+            let mut window = Window::default();
+            let center = Vec2::new(window.width(), window.height()) * 0.5;
+
+            // world y points up while the cursor's points down
+            window.set_cursor_position(Some(center + Vec2::new(30.0, -34.0)));
+            assert_eq!(
+                hovered_tile(&window, &camera, &map, tile_size),
+                Some((1, 1))
+            );
+            assert_eq!(
+                tile_details(1, 1, &map.tiles[1][1]),
+                format!(
+                    "(1, 1) Walkable\nObject: Door facing East\nFloor: Wall\n{}",
+                    map.tiles[1][1].connection.as_ref().unwrap()
+                )
+            );
+            assert_eq!(tile_details(0, 0, &map.tiles[0][0]), "(0, 0) Walkable");
+
+            window.set_cursor_position(Some(center + Vec2::new(-32.0, 0.0)));
+            assert_eq!(hovered_tile(&window, &camera, &map, tile_size), None);
+            window.set_cursor_position(None);
+            assert_eq!(hovered_tile(&window, &camera, &map, tile_size), None);
+        }
+
+        #[test]
+        fn objects_are_drawn_on_the_edge_they_face() {
+            let theme = Theme::default();
+            let tile_size = TileSize {
+                width: 40.0,
+                height: 20.0,
+            };
+
+            let door = object_sprite(
+                &Object::facing(ObjectType::Door, Direction::North),
+                &theme,
+                tile_size,
+            );
+            assert_eq!(door.sprite.color, theme.object_color(ObjectType::Door));
+            assert_eq!(door.sprite.custom_size, Some(Vec2::new(40.0, 4.0)));
+            // north is towards the first row, which is drawn at the bottom
+            assert!(door
+                .transform
+                .translation
+                .abs_diff_eq(Vec3::new(0.0, -8.0, 0.2), 1e-5));
+
+            let wall = object_sprite(
+                &Object::facing(ObjectType::Wall, Direction::East),
+                &theme,
+                tile_size,
+            );
+            assert_eq!(wall.sprite.color, theme.object_color(ObjectType::Wall));
+            assert!(wall
+                .transform
+                .translation
+                .abs_diff_eq(Vec3::new(16.0, 0.0, 0.2), 1e-5));
+            // turned upright, the bar spans the tile's height and is as thick as a fifth of its width
+            let span = wall.transform.rotation * wall.sprite.custom_size.unwrap().extend(0.0);
+            assert!(span.abs().abs_diff_eq(Vec3::new(8.0, 20.0, 0.0), 1e-5));
+
+            let turned = object_sprite(
+                &Object::new(ObjectType::Door, Quat::from_rotation_y(0.3)),
+                &theme,
+                tile_size,
+            );
+            assert_eq!(turned.transform.translation, Vec3::new(0.0, 0.0, 0.2));
         }
     }
 }
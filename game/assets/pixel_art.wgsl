@@ -8,14 +8,16 @@ var texture_sampler: sampler;
 struct PostProcessSettings {
     height: f32,
     width: f32,
+    pixel_size: f32,
+    enabled: f32,
+    tint: vec3<f32>,
 }
 @group(0) @binding(2)
 var<uniform> window_resolution : PostProcessSettings;
 
-const SCALE: f32 = 4.0;
-
 fn downsample(in: vec2<f32>) -> vec2<f32> {
-    return vec2<f32>(floor(in.x * window_resolution.width / SCALE) / window_resolution.width * SCALE, floor(in.y * window_resolution.height / SCALE) / window_resolution.height * SCALE);
+    let scale = window_resolution.pixel_size;
+    return vec2<f32>(floor(in.x * window_resolution.width / scale) / window_resolution.width * scale, floor(in.y * window_resolution.height / scale) / window_resolution.height * scale);
 }
 
 @fragment
@@ -25,6 +27,7 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
 
     // color quantize
     var color = textureSample(screen_texture, texture_sampler, uv);
+    color = vec4<f32>(color.rgb * window_resolution.tint, color.a);
     color = floor(color * 128.0) / 128.0;
 
     return vec4<f32>(color.rgb, 1.0);
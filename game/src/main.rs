@@ -1,24 +1,33 @@
-#![allow(clippy::type_complexity)]
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
 
-use std::time::Duration;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bevy::{
-    asset::ChangeWatcher,
+    asset::{ChangeWatcher, FileAssetIo},
     core_pipeline::{core_3d, fullscreen_vertex_shader::fullscreen_shader_vertex_state},
+    input::mouse::MouseWheel,
     prelude::*,
     render::{
         camera::ScalingMode,
         extract_component::{
             ComponentUniforms, ExtractComponent, ExtractComponentPlugin, UniformComponentPlugin,
         },
+        mesh::Indices,
         render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext},
         render_resource::{
             BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
             BindGroupLayoutEntry, BindingResource, BindingType, CachedRenderPipelineId,
             ColorTargetState, ColorWrites, FragmentState, MultisampleState, Operations,
-            PipelineCache, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
-            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
-            ShaderType, TextureFormat, TextureSampleType, TextureViewDimension,
+            PipelineCache, PrimitiveState, PrimitiveTopology, RenderPassColorAttachment,
+            RenderPassDescriptor, RenderPipelineDescriptor, Sampler, SamplerBindingType,
+            SamplerDescriptor, ShaderStages, ShaderType, TextureFormat, TextureSampleType,
+            TextureViewDimension,
         },
         renderer::{RenderContext, RenderDevice},
         texture::BevyDefault,
@@ -27,24 +36,69 @@ use bevy::{
     },
 };
 
-use map::{Map, TileType};
+use map::{Direction, Map, MultiFloorMap, ObjectType, Theme, TileSize, TileType};
+use serde::{Deserialize, Serialize};
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
     // Set up the Bevy app
     App::new()
         .add_plugins((
-            DefaultPlugins.set(AssetPlugin {
-                watch_for_changes: ChangeWatcher::with_delay(Duration::from_secs(1)),
-                ..default()
-            }),
+            DefaultPlugins
+                .set(AssetPlugin {
+                    watch_for_changes: ChangeWatcher::with_delay(Duration::from_secs(1)),
+                    ..default()
+                })
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        title: TITLE.into(),
+                        ..default()
+                    }),
+                    ..default()
+                }),
             PostProcessPlugin,
         ))
         .init_resource::<CurrentMap>()
+        .insert_resource(CurrentMapFile(
+            map_argument(&args).unwrap_or_default().to_string(),
+        ))
+        .init_resource::<MapDirectory>()
+        .init_resource::<Floors>()
+        .init_resource::<CurrentFloor>()
         .init_resource::<PlayerPosition>()
+        .insert_resource(WallMeshMode::from_args(&args))
+        .insert_resource(MovementLog::from_args(&args))
+        .insert_resource(LightingSettings::load())
+        .insert_resource(load_theme())
+        .init_resource::<TileSize>()
+        .init_resource::<CameraFollow>()
+        .init_resource::<KeyBindings>()
+        .init_resource::<PixelSize>()
         .add_systems(Startup, setup)
         .add_systems(
             Update,
-            ((player_movement, update_resolution), player_rotate).chain(),
+            (
+                (player_movement, update_resolution),
+                change_floor,
+                take_connection,
+                player_rotate,
+                camera_follow,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                (adjust_pixel_size, camera_zoom).chain(),
+                toggle_post_process,
+                update_title,
+                debug_overlay,
+            ),
+        )
+        .add_systems(
+            Update,
+            (adjust_lighting, apply_lighting, day_night_tint).chain(),
         )
         .run();
 }
@@ -55,15 +109,266 @@ struct Player;
 #[derive(Component)]
 struct Camera;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Interact,
+}
+
+impl Action {
+    const MOVES: [(Action, Direction); 4] = [
+        (Action::MoveUp, Direction::North),
+        (Action::MoveDown, Direction::South),
+        (Action::MoveLeft, Direction::West),
+        (Action::MoveRight, Direction::East),
+    ];
+}
+
+// Keys for each action, an action can have several so arrows can sit next to WASD
+#[derive(Resource, Clone, Debug, PartialEq)]
+struct KeyBindings(HashMap<Action, Vec<KeyCode>>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings(HashMap::from([
+            (Action::MoveUp, vec![KeyCode::W]),
+            (Action::MoveDown, vec![KeyCode::S]),
+            (Action::MoveLeft, vec![KeyCode::A]),
+            (Action::MoveRight, vec![KeyCode::D]),
+            (Action::Interact, vec![KeyCode::E]),
+        ]))
+    }
+}
+
+impl KeyBindings {
+    fn keys(&self, action: Action) -> &[KeyCode] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.any_pressed(self.keys(action).iter().copied())
+    }
+}
+
+// Where the camera sits relative to the player and how quickly it catches up, higher smoothing is snappier
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+struct CameraFollow {
+    offset: Vec3,
+    smoothing: f32,
+}
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        CameraFollow {
+            offset: Vec3::splat(5.0),
+            smoothing: 8.0,
+        }
+    }
+}
+
+// Text node listing the player's tile and its neighbors, toggled with F3
+#[derive(Component)]
+struct DebugOverlay;
+
+// The direction the player last moved in, kept while standing still
+#[derive(Component)]
+struct Facing(Direction);
+
 #[derive(Resource, Default)]
 struct CurrentMap(Map);
 
+// Every floor of the loaded world, CurrentMap holds a copy of the active one
+#[derive(Resource, Default)]
+struct Floors(MultiFloorMap);
+
+#[derive(Resource, Default)]
+struct CurrentFloor(usize);
+
+// Floor and wall entities of the active floor
+#[derive(Component)]
+struct LevelGeometry;
+
+// Name of the file the current map was loaded from, starts as the map given on the command line if any
+#[derive(Resource, Default)]
+struct CurrentMapFile(String);
+
+const TITLE: &str = "Private Collection";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct MoveRecord {
+    direction: Direction,
+    // (row, column) the move ended on
+    position: (usize, usize),
+    map: String,
+}
+
+// Committed moves get appended to `record_to` as json lines, `replay` is fed back into movement instead of input
+#[derive(Resource, Default)]
+struct MovementLog {
+    record_to: Option<PathBuf>,
+    replay: VecDeque<MoveRecord>,
+}
+
+impl MovementLog {
+    fn from_args(args: &[String]) -> Self {
+        let mut log = MovementLog {
+            record_to: flag_value(args, "--record").map(PathBuf::from),
+            ..default()
+        };
+
+        if let Some(path) = flag_value(args, "--replay") {
+            match load_movement_log(Path::new(path)) {
+                Ok(records) => log.replay = records.into(),
+                Err(error) => println!("Failed to load movement log {}: {}", path, error),
+            }
+        }
+
+        log
+    }
+
+    fn record(&self, record: &MoveRecord) {
+        let Some(path) = &self.record_to else {
+            return;
+        };
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(record).map_err(std::io::Error::from)?;
+                writeln!(file, "{}", line)
+            });
+
+        if let Err(error) = result {
+            println!("Failed to record move: {}", error);
+        }
+    }
+}
+
+fn load_movement_log(path: &Path) -> std::io::Result<Vec<MoveRecord>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(std::io::Error::from))
+        .collect()
+}
+
+// Flags followed by a value, so the value isn't taken for a positional argument
+const VALUE_FLAGS: [&str; 2] = ["--record", "--replay"];
+
+// The first argument that isn't a flag or a flag's value, e.g. the map in `cargo run -- level.map`
+fn map_argument(args: &[String]) -> Option<&str> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with("--") {
+            return Some(arg);
+        }
+    }
+    None
+}
+
+// The argument following `flag`, e.g. `--record moves.log`
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
+}
+
+// Grid position of the player as (row, column)
 #[derive(Resource, Default)]
 struct PlayerPosition {
     pub pos: (usize, usize),
     pub timer: Timer,
 }
 
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
+enum WallMeshMode {
+    // Only the wall faces that border a walkable tile, merged into a single mesh
+    #[default]
+    Culled,
+    // A full cube for every wall tile
+    Cubes,
+}
+
+impl WallMeshMode {
+    // `--cube-walls` falls back to a cube per wall tile
+    fn from_args(args: &[String]) -> Self {
+        if args.iter().any(|arg| arg == "--cube-walls") {
+            WallMeshMode::Cubes
+        } else {
+            WallMeshMode::Culled
+        }
+    }
+
+    // How many triangles the walls of `map` take in this mode
+    fn triangles(self, map: &Map) -> usize {
+        match self {
+            WallMeshMode::Culled => wall_mesh_builder(map).indices.len() / 3,
+            WallMeshMode::Cubes => {
+                map.tiles
+                    .iter()
+                    .flatten()
+                    .filter(|tile| tile.is_wall())
+                    .count()
+                    * 12
+            }
+        }
+    }
+}
+
+// Rows run along +z and columns along +x, so tile (row, column) is centered on
+// (column * width, 0, row * height) and North (row - 1) faces -z
+fn grid_to_world(pos: (usize, usize), tile_size: TileSize) -> Vec3 {
+    let center = tile_size.tile_to_world(pos.1 as i32, pos.0 as i32);
+    Vec3::new(center.x, 0.0, center.y)
+}
+
+// Scale that stretches a one unit tile mesh to the tile size
+fn tile_scale(tile_size: TileSize) -> Vec3 {
+    Vec3::new(tile_size.width, 1.0, tile_size.height)
+}
+
+// tile_scale for a mesh turned by `rotation`, whose scale applies before the turn. A door facing east
+// spans the tile along z, so its x has to stretch to the tile's height rather than its width
+fn rotated_tile_scale(rotation: Quat, tile_size: TileSize) -> Vec3 {
+    let extent = |axis: Vec3| {
+        let axis = rotation * axis;
+        axis.x.abs() * tile_size.width + axis.z.abs() * tile_size.height
+    };
+    Vec3::new(extent(Vec3::X), 1.0, extent(Vec3::Z))
+}
+
+const DEFAULT_ZOOM: f32 = 4.0;
+const MIN_ZOOM: f32 = 2.0;
+const MAX_ZOOM: f32 = 8.0;
+
+// How far an analog stick has to be pushed before it moves the player
+const STICK_DEADZONE: f32 = 0.5;
+
+// Size of a "pixel" on screen at the default zoom level
+const PIXEL_SIZE: f32 = 4.0;
+// 1 shows every screen pixel, so no pixelation, past 16 the scene stops being readable
+const MIN_PIXEL_SIZE: f32 = 1.0;
+const MAX_PIXEL_SIZE: f32 = 16.0;
+
+// The pixel size at the default zoom level, starts at PIXEL_SIZE and is changed with PageUp and PageDown.
+// Stays within MIN_PIXEL_SIZE..=MAX_PIXEL_SIZE, camera_zoom scales it into PostProcessSettings
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+struct PixelSize(f32);
+
+impl Default for PixelSize {
+    fn default() -> Self {
+        PixelSize(PIXEL_SIZE)
+    }
+}
+
 struct PostProcessPlugin;
 
 impl Plugin for PostProcessPlugin {
@@ -129,7 +434,7 @@ impl Plugin for PostProcessPlugin {
 }
 
 struct PostProcessNode {
-    query: QueryState<&'static ViewTarget, With<ExtractedView>>,
+    query: QueryState<(&'static ViewTarget, &'static PostProcessSettings), With<ExtractedView>>,
 }
 
 impl PostProcessNode {
@@ -160,10 +465,15 @@ impl Node for PostProcessNode {
 
         // We get the data we need from the world based on the view entity passed to the node.
         // The data is the query that was defined earlier in the [`PostProcessNode`]
-        let Ok(view_target) = self.query.get_manual(world, view_entity) else {
+        let Ok((view_target, settings)) = self.query.get_manual(world, view_entity) else {
             return Ok(());
         };
 
+        // Skipping the post process write leaves the main texture as the raw render
+        if !settings.is_enabled() {
+            return Ok(());
+        }
+
         // Get the pipeline resource that contains the global data we need to create the render pipeline
         let post_process_pipeline = world.resource::<PostProcessPipeline>();
 
@@ -331,6 +641,99 @@ impl FromWorld for PostProcessPipeline {
 struct PostProcessSettings {
     height: f32,
     width: f32,
+    // screen pixels per pixel art pixel, 1 or more
+    pixel_size: f32,
+    // 1 runs the effect and 0 skips it, a float because uniforms can't hold bools
+    enabled: f32,
+    // multiplied into the final color, white leaves it untouched
+    tint: Vec3,
+}
+
+impl PostProcessSettings {
+    fn is_enabled(&self) -> bool {
+        self.enabled != 0.0
+    }
+}
+
+const SETTINGS_FILE: &str = "settings.json";
+const THEME_FILE: &str = "theme.json";
+
+// Within the asset folder, where the built in map's connections look up the map they name
+const MAPS_DIR: &str = "assets/maps";
+
+// Directory of the file the current map came from, connections name maps relative to it like in the editor
+#[derive(Resource, Default)]
+struct MapDirectory(PathBuf);
+
+fn map_directory(map_file: &Path) -> PathBuf {
+    map_file
+        .parent()
+        .map(|directory| directory.to_path_buf())
+        .unwrap_or_default()
+}
+
+// Whether the player stepped onto a new tile since the last call, the first call only remembers where they start
+fn arrived(last_position: &mut Option<(usize, usize)>, position: (usize, usize)) -> bool {
+    let previous = last_position.replace(position);
+    previous.is_some_and(|previous| previous != position)
+}
+
+fn load_theme() -> Theme {
+    if !Path::new(THEME_FILE).exists() {
+        return Theme::default();
+    }
+
+    Theme::load_from_path(THEME_FILE).unwrap_or_else(|error| {
+        println!("Failed to load {}: {}", THEME_FILE, error);
+        Theme::default()
+    })
+}
+const DAY_LENGTH: f32 = 120.0;
+const NIGHT_TINT: Vec3 = Vec3::new(0.75, 0.8, 1.0);
+
+// Player lighting preferences, saved to SETTINGS_FILE whenever they change
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+struct LightingSettings {
+    ambient_color: [f32; 3],
+    ambient_brightness: f32,
+    light_color: [f32; 3],
+    light_intensity: f32,
+    day_night: bool,
+}
+
+impl Default for LightingSettings {
+    // matches bevy's default AmbientLight and PointLight
+    fn default() -> Self {
+        LightingSettings {
+            ambient_color: [1.0, 1.0, 1.0],
+            ambient_brightness: 0.05,
+            light_color: [1.0, 1.0, 1.0],
+            light_intensity: 800.0,
+            day_night: false,
+        }
+    }
+}
+
+impl LightingSettings {
+    fn load() -> Self {
+        match std::fs::read_to_string(SETTINGS_FILE) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|error| {
+                println!("Failed to parse {}: {}", SETTINGS_FILE, error);
+                LightingSettings::default()
+            }),
+            Err(_) => LightingSettings::default(),
+        }
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| std::fs::write(SETTINGS_FILE, contents));
+
+        if let Err(error) = result {
+            println!("Failed to save {}: {}", SETTINGS_FILE, error);
+        }
+    }
 }
 
 fn setup(
@@ -338,36 +741,60 @@ fn setup(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut map: ResMut<CurrentMap>,
+    mut map_file: ResMut<CurrentMapFile>,
+    mut directory: ResMut<MapDirectory>,
     asset_server: Res<AssetServer>,
     mut position: ResMut<PlayerPosition>,
+    wall_mode: Res<WallMeshMode>,
+    theme: Res<Theme>,
+    tile_size: Res<TileSize>,
+    mut floors: ResMut<Floors>,
+    mut current_floor: ResMut<CurrentFloor>,
+    follow: Res<CameraFollow>,
 ) {
+    let tile_size = *tile_size;
+
     // camera
     commands.spawn((
         Camera3dBundle {
             projection: OrthographicProjection {
-                scale: 4.0,
+                scale: DEFAULT_ZOOM,
                 scaling_mode: ScalingMode::FixedVertical(2.0),
                 ..default()
             }
             .into(),
-            transform: Transform::from_xyz(5.0, 5.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+            transform: Transform::from_translation(follow.offset).looking_at(Vec3::ZERO, Vec3::Y),
             ..default()
         },
         Camera,
         PostProcessSettings {
             height: 720.0,
             width: 1280.0,
+            pixel_size: PIXEL_SIZE,
+            enabled: 1.0,
+            tint: Vec3::ONE,
         },
     ));
 
-    // player
     commands.spawn((
-        SceneBundle {
-            scene: asset_server.load("models/character.gltf#Scene0"),
-            transform: Transform::from_xyz(1.0, 0.0, 1.0),
-            ..Default::default()
+        TextBundle {
+            visibility: Visibility::Hidden,
+            ..TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            })
         },
-        Player,
+        DebugOverlay,
     ));
 
     // light
@@ -376,97 +803,497 @@ fn setup(
         ..default()
     });
 
-    // get map from maps/init.map then decode using serde_json
-    let map_str = include_str!("../assets/maps/init.map");
-    let map_json: Map = serde_json::from_str(map_str).unwrap();
+    // the map from the command line, otherwise maps/init.map which is built in. Either can hold several floors,
+    // the player starts on the first
+    let loaded = match map_file.0.as_str() {
+        "" => None,
+        path => MultiFloorMap::load_from_path(path)
+            .map_err(|error| println!("Failed to load {}: {}", path, error))
+            .ok(),
+    };
+    directory.0 = map_directory(Path::new(&map_file.0));
+    let world = loaded.unwrap_or_else(|| {
+        map_file.0 = "init.map".to_string();
+        directory.0 = FileAssetIo::get_base_path().join(MAPS_DIR);
+        let map_str = include_str!("../assets/maps/init.map");
+        MultiFloorMap::load_detect(map_str.as_bytes()).unwrap_or_else(|error| {
+            println!("{}", error);
+            MultiFloorMap::from(Map::default())
+        })
+    });
+    let map_json = world.floors[0].clone();
     map.0 = map_json.clone();
 
     println!("{:?}", map_json);
 
-    for (i, row) in map_json.tiles.iter().enumerate() {
+    // player, on the map's spawn as (row, column)
+    let spawn = map_json
+        .default_spawn()
+        .map(|(x, y)| (y, x))
+        .unwrap_or_default();
+    commands.spawn((
+        SceneBundle {
+            scene: asset_server.load("models/character.gltf#Scene0"),
+            transform: Transform::from_translation(grid_to_world(spawn, tile_size)),
+            ..Default::default()
+        },
+        Player,
+        Facing(Direction::South),
+    ));
+
+    spawn_level(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &map_json,
+        &theme,
+        *wall_mode,
+        tile_size,
+    );
+    floors.0 = world;
+    current_floor.0 = 0;
+
+    position.timer = Timer::from_seconds(0.15, TimerMode::Repeating);
+    position.pos = spawn;
+}
+
+// Floor tiles, walls and tile objects of the active map, tagged so they can be swapped out when changing floors
+fn spawn_level(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    map: &Map,
+    theme: &Theme,
+    wall_mode: WallMeshMode,
+    tile_size: TileSize,
+) {
+    // every floor tile shares one mesh and tiles of the same type share a material so they can be batched
+    let floor_mesh = meshes.add(shape::Plane::from_size(1.0).into());
+    let floor_materials: HashMap<TileType, Handle<StandardMaterial>> = TileType::ALL
+        .into_iter()
+        .filter(|&tile_type| tile_type != TileType::Blocked)
+        .map(|tile_type| {
+            let material = materials.add(StandardMaterial {
+                base_color: theme.tile_color(tile_type),
+                ..Default::default()
+            });
+            (tile_type, material)
+        })
+        .collect();
+    let wall_material = materials.add(StandardMaterial {
+        base_color: theme.object_color(ObjectType::Wall),
+        ..Default::default()
+    });
+    let origin_material = theme.origin_marker().map(|color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            ..Default::default()
+        })
+    });
+
+    for (i, row) in map.tiles.iter().enumerate() {
         for (j, tile) in row.iter().enumerate() {
-            if tile.tile_type == TileType::Walkable {
-                commands.spawn(PbrBundle {
-                    mesh: meshes.add(shape::Plane::from_size(1.0).into()),
-                    material: materials.add(StandardMaterial {
-                        base_color: if i == 0 && j == 0 {
-                            Color::rgb(0.0, 0.0, 0.0)
-                        } else {
-                            Color::rgb(1.0, 1.0, 1.0)
+            if let Some(floor_material) = floor_materials.get(&tile.tile_type) {
+                commands.spawn((
+                    PbrBundle {
+                        mesh: floor_mesh.clone(),
+                        material: match &origin_material {
+                            Some(origin_material) if i == 0 && j == 0 => origin_material.clone(),
+                            _ => floor_material.clone(),
                         },
+                        transform: Transform::from_translation(grid_to_world((i, j), tile_size))
+                            .with_scale(tile_scale(tile_size)),
                         ..Default::default()
-                    }),
-                    transform: Transform::from_xyz(j as f32, 0.0, i as f32),
+                    },
+                    LevelGeometry,
+                ));
+            }
+        }
+    }
+
+    match wall_mode {
+        WallMeshMode::Culled => {
+            commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(wall_mesh(map)),
+                    material: wall_material,
+                    transform: Transform::from_scale(tile_scale(tile_size)),
                     ..Default::default()
-                });
+                },
+                LevelGeometry,
+            ));
+        }
+        WallMeshMode::Cubes => {
+            let cube = meshes.add(shape::Cube::new(1.0).into());
+
+            for (i, row) in map.tiles.iter().enumerate() {
+                for (j, tile) in row.iter().enumerate() {
+                    if tile.is_wall() {
+                        commands.spawn((
+                            PbrBundle {
+                                mesh: cube.clone(),
+                                material: wall_material.clone(),
+                                transform: Transform::from_translation(
+                                    grid_to_world((i, j), tile_size) + Vec3::Y * 0.5,
+                                )
+                                .with_scale(tile_scale(tile_size)),
+                                ..Default::default()
+                            },
+                            LevelGeometry,
+                        ));
+                    }
+                }
             }
+
+            info!(
+                "Spawned wall cubes with {} triangles",
+                wall_mode.triangles(map)
+            );
         }
     }
 
-    position.timer = Timer::from_seconds(0.15, TimerMode::Repeating);
-    position.pos = (1, 1);
+    spawn_tile_entities(commands, meshes, materials, map, theme, tile_size);
 }
 
-fn player_movement(
-    keyboard_input: Res<Input<KeyCode>>,
-    map: Res<CurrentMap>,
-    mut query: Query<&mut Transform, Or<(With<Player>, With<Camera>)>>,
-    mut position: ResMut<PlayerPosition>,
-    time: Res<Time>,
+const DOOR_THICKNESS: f32 = 0.1;
+const FLOOR_OBJECT_SIZE: f32 = 0.6;
+
+// Doors and floor objects of every tile. Wall objects already make their tile part of the wall geometry.
+// A door is a panel across the tile, turned by its rotation so one facing north spans the tile east to west
+fn spawn_tile_entities(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    map: &Map,
+    theme: &Theme,
+    tile_size: TileSize,
 ) {
-    if position.timer.tick(time.delta()).finished() {
-        if keyboard_input.pressed(KeyCode::W)
-            && map.0.tiles[position.pos.0 - 1][position.pos.1].tile_type == TileType::Walkable
-        {
-            position.pos.0 -= 1;
-            for mut transform in query.iter_mut() {
-                transform.translation.z -= 1.0;
+    let door_mesh = meshes.add(shape::Box::new(1.0, 1.0, DOOR_THICKNESS).into());
+    let floor_object_mesh = meshes.add(shape::Plane::from_size(FLOOR_OBJECT_SIZE).into());
+    let object_materials: HashMap<ObjectType, Handle<StandardMaterial>> = ObjectType::ALL
+        .into_iter()
+        .map(|object_type| {
+            let material = materials.add(StandardMaterial {
+                base_color: theme.object_color(object_type),
+                ..Default::default()
+            });
+            (object_type, material)
+        })
+        .collect();
+
+    for (i, row) in map.tiles.iter().enumerate() {
+        for (j, tile) in row.iter().enumerate() {
+            let center = grid_to_world((i, j), tile_size);
+
+            for object in tile
+                .objects()
+                .filter(|object| object.object_type == ObjectType::Door)
+            {
+                commands.spawn((
+                    PbrBundle {
+                        mesh: door_mesh.clone(),
+                        material: object_materials[&object.object_type].clone(),
+                        transform: Transform::from_translation(center + Vec3::Y * 0.5)
+                            .with_rotation(object.rotation)
+                            .with_scale(rotated_tile_scale(object.rotation, tile_size)),
+                        ..Default::default()
+                    },
+                    LevelGeometry,
+                ));
             }
-        } else if keyboard_input.pressed(KeyCode::S)
-            && map.0.tiles[position.pos.0 + 1][position.pos.1].tile_type == TileType::Walkable
-        {
-            position.pos.0 += 1;
-            for mut transform in query.iter_mut() {
-                transform.translation.z += 1.0;
+
+            // just above the floor so it doesn't z-fight
+            if let Some(floor_object) = &tile.floor_object {
+                commands.spawn((
+                    PbrBundle {
+                        mesh: floor_object_mesh.clone(),
+                        material: object_materials[&floor_object.object_type].clone(),
+                        transform: Transform::from_translation(center + Vec3::Y * 0.01)
+                            .with_scale(tile_scale(tile_size)),
+                        ..Default::default()
+                    },
+                    LevelGeometry,
+                ));
             }
-        } else if keyboard_input.pressed(KeyCode::A)
-            && map.0.tiles[position.pos.0][position.pos.1 - 1].tile_type == TileType::Walkable
-        {
-            position.pos.1 -= 1;
-            for mut transform in query.iter_mut() {
-                transform.translation.x -= 1.0;
+        }
+    }
+}
+
+#[derive(Default)]
+struct WallMeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl WallMeshBuilder {
+    // Adds a unit quad centered on `center` facing along `normal`, wound counter-clockwise
+    fn push_quad(&mut self, center: Vec3, normal: Vec3) {
+        let up = if normal == Vec3::Y {
+            Vec3::NEG_Z
+        } else {
+            Vec3::Y
+        };
+        let u = up.cross(normal) * 0.5;
+        let v = up * 0.5;
+
+        let start = self.positions.len() as u32;
+        for (corner, uv) in [
+            (center - u - v, [0.0, 1.0]),
+            (center + u - v, [1.0, 1.0]),
+            (center + u + v, [1.0, 0.0]),
+            (center - u + v, [0.0, 0.0]),
+        ] {
+            self.positions.push(corner.to_array());
+            self.normals.push(normal.to_array());
+            self.uvs.push(uv);
+        }
+        self.indices
+            .extend([start, start + 1, start + 2, start, start + 2, start + 3]);
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+// Builds one mesh holding only the wall faces that can actually be seen from a walkable tile
+fn wall_mesh(map: &Map) -> Mesh {
+    let builder = wall_mesh_builder(map);
+
+    info!(
+        "Built wall mesh with {} triangles",
+        builder.indices.len() / 3
+    );
+
+    builder.build()
+}
+
+fn wall_mesh_builder(map: &Map) -> WallMeshBuilder {
+    let mut builder = WallMeshBuilder::default();
+
+    for (i, row) in map.tiles.iter().enumerate() {
+        for (j, tile) in row.iter().enumerate() {
+            if !tile.is_wall() {
+                continue;
             }
-        } else if keyboard_input.pressed(KeyCode::D)
-            && map.0.tiles[position.pos.0][position.pos.1 + 1].tile_type == TileType::Walkable
-        {
-            position.pos.1 += 1;
-            for mut transform in query.iter_mut() {
-                transform.translation.x += 1.0;
+
+            // built at one unit per tile and scaled to the tile size when spawned
+            let center = grid_to_world((i, j), TileSize::default()) + Vec3::Y * 0.5;
+            let mut exposed = false;
+
+            for (direction, _, neighbor) in map.neighbors(j, i) {
+                if neighbor.is_wall() {
+                    continue;
+                }
+
+                let (dx, dy) = direction.offset();
+                let normal = Vec3::new(dx as f32, 0.0, dy as f32);
+                builder.push_quad(center + normal * 0.5, normal);
+                exposed = true;
             }
+
+            if exposed {
+                builder.push_quad(center + Vec3::Y * 0.5, Vec3::Y);
+            }
+        }
+    }
+
+    builder
+}
+
+// The (row, column) the player ends up on after stepping in `direction`, if that tile can be walked on
+fn try_move(map: &Map, pos: (usize, usize), direction: Direction) -> Option<(usize, usize)> {
+    map.neighbor(pos.1, pos.0, direction)
+        .map(|(position, _)| position)
+        .filter(|&(x, y)| map.is_walkable(x, y))
+        .map(|(x, y)| (y, x))
+}
+
+// Snaps an analog stick to the closest grid direction, ignoring small movements inside the deadzone
+fn quantize_stick(stick: Vec2) -> Option<Direction> {
+    if stick.length() < STICK_DEADZONE {
+        return None;
+    }
+
+    Some(if stick.x.abs() > stick.y.abs() {
+        if stick.x > 0.0 {
+            Direction::East
         } else {
-            return;
+            Direction::West
         }
+    } else if stick.y > 0.0 {
+        Direction::North
+    } else {
+        Direction::South
+    })
+}
 
-        position.timer.reset();
+fn gamepad_direction(
+    gamepads: &Gamepads,
+    buttons: &Input<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+) -> Option<Direction> {
+    for gamepad in gamepads.iter() {
+        for (button, direction) in [
+            (GamepadButtonType::DPadUp, Direction::North),
+            (GamepadButtonType::DPadDown, Direction::South),
+            (GamepadButtonType::DPadLeft, Direction::West),
+            (GamepadButtonType::DPadRight, Direction::East),
+        ] {
+            if buttons.pressed(GamepadButton::new(gamepad, button)) {
+                return Some(direction);
+            }
+        }
+
+        let stick = Vec2::new(
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+                .unwrap_or(0.0),
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+                .unwrap_or(0.0),
+        );
+        if let Some(direction) = quantize_stick(stick) {
+            return Some(direction);
+        }
     }
+
+    None
 }
 
-fn player_rotate(
+fn player_movement(
     keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<&mut Transform, With<Player>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    map: Res<CurrentMap>,
+    mut player: Query<&mut Transform, With<Player>>,
+    mut facing: Query<&mut Facing, With<Player>>,
+    mut position: ResMut<PlayerPosition>,
+    mut log: ResMut<MovementLog>,
+    map_file: Res<CurrentMapFile>,
+    bindings: Res<KeyBindings>,
+    tile_size: Res<TileSize>,
+    time: Res<Time>,
 ) {
-    for mut transform in query.iter_mut() {
-        if keyboard_input.pressed(KeyCode::W) {
-            transform.rotation = Quat::from_rotation_y(std::f32::consts::PI);
+    if position.timer.tick(time.delta()).finished() {
+        let expected = log.replay.pop_front();
+
+        let directions: Vec<Direction> = match &expected {
+            Some(record) => vec![record.direction],
+            None => Action::MOVES
+                .into_iter()
+                .filter(|&(action, _)| bindings.pressed(&keyboard_input, action))
+                .map(|(_, direction)| direction)
+                .chain(gamepad_direction(
+                    &gamepads,
+                    &gamepad_buttons,
+                    &gamepad_axes,
+                ))
+                .collect(),
+        };
+
+        let Some((direction, pos)) = directions.into_iter().find_map(|direction| {
+            try_move(&map.0, position.pos, direction).map(|pos| (direction, pos))
+        }) else {
+            if let Some(expected) = expected {
+                println!(
+                    "Replay diverged: {:?} from {:?} was blocked",
+                    expected.direction, position.pos
+                );
+            }
+            return;
+        };
+
+        if let Some(expected) = expected {
+            if expected.position != pos {
+                println!(
+                    "Replay diverged: expected to reach {:?} but reached {:?}",
+                    expected.position, pos
+                );
+            }
         }
-        if keyboard_input.pressed(KeyCode::S) {
-            transform.rotation = Quat::from_rotation_y(0.0);
+        log.record(&MoveRecord {
+            direction,
+            position: pos,
+            map: map_file.0.clone(),
+        });
+
+        // the player snaps to its tile so it can't drift from the grid, camera_follow catches up
+        for mut transform in player.iter_mut() {
+            debug_assert_eq!(
+                transform.translation,
+                grid_to_world(position.pos, *tile_size),
+                "player transform out of sync with {:?}",
+                position.pos
+            );
+            transform.translation = grid_to_world(pos, *tile_size);
         }
-        if keyboard_input.pressed(KeyCode::A) {
-            transform.rotation = Quat::from_rotation_y(std::f32::consts::PI * 1.5);
+        position.pos = pos;
+        for mut facing in facing.iter_mut() {
+            facing.0 = direction;
         }
-        if keyboard_input.pressed(KeyCode::D) {
-            transform.rotation = Quat::from_rotation_y(std::f32::consts::PI / 2.0);
+
+        position.timer.reset();
+    }
+}
+
+// Moves `current` towards `target` by the fraction `smoothing` covers in `delta` seconds, so the camera
+// eases in the same way at any frame rate. Non-positive smoothing snaps straight to the target
+fn follow_step(current: Vec3, target: Vec3, smoothing: f32, delta: f32) -> Vec3 {
+    if smoothing <= 0.0 {
+        return target;
+    }
+    current.lerp(target, 1.0 - (-smoothing * delta).exp())
+}
+
+fn camera_follow(
+    follow: Res<CameraFollow>,
+    player: Query<&Transform, With<Player>>,
+    mut camera: Query<&mut Transform, (With<Camera>, Without<Player>)>,
+    time: Res<Time>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+
+    let target = player.translation + follow.offset;
+    for mut transform in camera.iter_mut() {
+        transform.translation = follow_step(
+            transform.translation,
+            target,
+            follow.smoothing,
+            time.delta_seconds(),
+        );
+        let focus = transform.translation - follow.offset;
+        transform.look_at(focus, Vec3::Y);
+    }
+}
+
+fn facing_rotation(direction: Direction) -> Quat {
+    match direction {
+        Direction::North => Quat::from_rotation_y(std::f32::consts::PI),
+        Direction::South => Quat::from_rotation_y(0.0),
+        Direction::West => Quat::from_rotation_y(std::f32::consts::PI * 1.5),
+        Direction::East => Quat::from_rotation_y(std::f32::consts::PI / 2.0),
+    }
+}
+
+fn player_rotate(mut query: Query<(&mut Transform, &Facing), (With<Player>, Changed<Facing>)>) {
+    for (mut transform, facing) in query.iter_mut() {
+        transform.rotation = facing_rotation(facing.0);
+    }
+}
+
+fn update_title(map_file: Res<CurrentMapFile>, mut windows: Query<&mut Window>) {
+    if map_file.is_changed() {
+        for mut window in windows.iter_mut() {
+            window.title = format!("{} — {}", TITLE, map_file.0);
         }
     }
 }
@@ -482,3 +1309,630 @@ fn update_resolution(
         }
     }
 }
+
+fn camera_zoom(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut scroll: EventReader<MouseWheel>,
+    pixel_size: Res<PixelSize>,
+    mut camera: Query<(&mut Projection, &mut PostProcessSettings), With<Camera>>,
+) {
+    let mut delta: f32 = scroll.iter().map(|event| event.y).sum();
+    if keyboard_input.any_just_pressed([KeyCode::Equals, KeyCode::NumpadAdd]) {
+        delta -= 1.0;
+    }
+    if keyboard_input.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        delta += 1.0;
+    }
+
+    if delta == 0.0 && !pixel_size.is_changed() {
+        return;
+    }
+
+    for (mut projection, mut settings) in camera.iter_mut() {
+        if let Projection::Orthographic(orthographic) = projection.as_mut() {
+            orthographic.scale =
+                (orthographic.scale + delta * orthographic.scale * 0.1).clamp(MIN_ZOOM, MAX_ZOOM);
+
+            // keep the pixels the same size in world space so the pixel art look doesn't change
+            settings.pixel_size =
+                (pixel_size.0 * DEFAULT_ZOOM / orthographic.scale).max(MIN_PIXEL_SIZE);
+        }
+    }
+}
+
+// F4 flips between the pixel art look and the raw render to compare them
+fn toggle_post_process(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut post_process_settings: Query<&mut PostProcessSettings>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F4) {
+        return;
+    }
+
+    for mut settings in &mut post_process_settings {
+        settings.enabled = if settings.is_enabled() { 0.0 } else { 1.0 };
+    }
+}
+
+fn adjust_pixel_size(keyboard_input: Res<Input<KeyCode>>, mut pixel_size: ResMut<PixelSize>) {
+    let mut changed = pixel_size.0;
+    if keyboard_input.just_pressed(KeyCode::PageUp) {
+        changed += 1.0;
+    }
+    if keyboard_input.just_pressed(KeyCode::PageDown) {
+        changed -= 1.0;
+    }
+    changed = changed.clamp(MIN_PIXEL_SIZE, MAX_PIXEL_SIZE);
+
+    if changed != pixel_size.0 {
+        pixel_size.0 = changed;
+    }
+}
+
+fn adjust_lighting(keyboard_input: Res<Input<KeyCode>>, mut settings: ResMut<LightingSettings>) {
+    let mut changed = *settings;
+    if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        changed.light_intensity *= 1.25;
+    }
+    if keyboard_input.just_pressed(KeyCode::BracketLeft) {
+        changed.light_intensity /= 1.25;
+    }
+    if keyboard_input.just_pressed(KeyCode::Period) {
+        changed.ambient_brightness = (changed.ambient_brightness + 0.05).min(1.0);
+    }
+    if keyboard_input.just_pressed(KeyCode::Comma) {
+        changed.ambient_brightness = (changed.ambient_brightness - 0.05).max(0.0);
+    }
+    if keyboard_input.just_pressed(KeyCode::N) {
+        changed.day_night = !changed.day_night;
+    }
+
+    if changed != *settings {
+        *settings = changed;
+        settings.save();
+    }
+}
+
+fn apply_lighting(
+    settings: Res<LightingSettings>,
+    mut ambient: ResMut<AmbientLight>,
+    mut lights: Query<&mut PointLight>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    let [r, g, b] = settings.ambient_color;
+    ambient.color = Color::rgb(r, g, b);
+    ambient.brightness = settings.ambient_brightness;
+
+    let [r, g, b] = settings.light_color;
+    for mut light in &mut lights {
+        light.color = Color::rgb(r, g, b);
+        light.intensity = settings.light_intensity;
+    }
+}
+
+fn day_night_tint(
+    time: Res<Time>,
+    settings: Res<LightingSettings>,
+    mut post_process_settings: Query<&mut PostProcessSettings>,
+) {
+    let tint = if settings.day_night {
+        // 0 at midday, 1 at midnight
+        let night =
+            (1.0 - (time.elapsed_seconds() / DAY_LENGTH * std::f32::consts::TAU).cos()) / 2.0;
+        Vec3::ONE.lerp(NIGHT_TINT, night)
+    } else {
+        Vec3::ONE
+    };
+
+    for mut setting in &mut post_process_settings {
+        if setting.tint != tint {
+            setting.tint = tint;
+        }
+    }
+}
+
+fn debug_overlay(
+    keyboard_input: Res<Input<KeyCode>>,
+    map: Res<CurrentMap>,
+    position: Res<PlayerPosition>,
+    mut overlay: Query<(&mut Text, &mut Visibility), With<DebugOverlay>>,
+) {
+    for (mut text, mut visibility) in &mut overlay {
+        if keyboard_input.just_pressed(KeyCode::F3) {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Inherited,
+                _ => Visibility::Hidden,
+            };
+        }
+
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        let (row, column) = position.pos;
+        let mut lines = vec![format!("Position: (row {}, column {})", row, column)];
+        for direction in Direction::ALL {
+            lines.push(match map.0.neighbor(column, row, direction) {
+                Some(((x, y), tile)) => format!(
+                    "{:?}: {:?}, {}",
+                    direction,
+                    tile.tile_type,
+                    if map.0.is_walkable(x, y) {
+                        "passable"
+                    } else {
+                        "blocked"
+                    }
+                ),
+                None => format!("{:?}: out of bounds", direction),
+            });
+        }
+
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+// Stepping onto stairs swaps in the floor they lead to and moves the player to their spawn
+fn change_floor(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    floors: Res<Floors>,
+    mut current_floor: ResMut<CurrentFloor>,
+    mut map: ResMut<CurrentMap>,
+    mut position: ResMut<PlayerPosition>,
+    mut last_position: Local<Option<(usize, usize)>>,
+    level: Query<Entity, With<LevelGeometry>>,
+    mut player: Query<&mut Transform, With<Player>>,
+    theme: Res<Theme>,
+    wall_mode: Res<WallMeshMode>,
+    tile_size: Res<TileSize>,
+) {
+    // only react to actually arriving on a tile, otherwise stairs that spawn onto stairs would bounce forever
+    if !arrived(&mut last_position, position.pos) {
+        return;
+    }
+
+    let (row, column) = position.pos;
+    let Some((floor, (x, y))) = floors.0.take_stairs(current_floor.0, column, row) else {
+        return;
+    };
+
+    for entity in &level {
+        commands.entity(entity).despawn_recursive();
+    }
+    current_floor.0 = floor;
+    map.0 = floors.0.floors[floor].clone();
+    spawn_level(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &map.0,
+        &theme,
+        *wall_mode,
+        *tile_size,
+    );
+
+    for mut transform in player.iter_mut() {
+        transform.translation = grid_to_world((y, x), *tile_size);
+    }
+    position.pos = (y, x);
+    *last_position = Some(position.pos);
+}
+
+// Stepping onto a connection to another map loads that map from MapDirectory and moves the player to its spawn,
+// a target that fails to load or a spawn that can't be stood on leaves the player where they are
+fn take_connection(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut floors: ResMut<Floors>,
+    mut current_floor: ResMut<CurrentFloor>,
+    mut map: ResMut<CurrentMap>,
+    mut map_file: ResMut<CurrentMapFile>,
+    mut directory: ResMut<MapDirectory>,
+    mut position: ResMut<PlayerPosition>,
+    mut last_position: Local<Option<(usize, usize)>>,
+    level: Query<Entity, With<LevelGeometry>>,
+    mut player: Query<&mut Transform, With<Player>>,
+    theme: Res<Theme>,
+    wall_mode: Res<WallMeshMode>,
+    tile_size: Res<TileSize>,
+) {
+    // same as change_floor, arriving on the linked door on the other side mustn't send the player straight back
+    if !arrived(&mut last_position, position.pos) {
+        return;
+    }
+
+    let (row, column) = position.pos;
+    // stairs are change_floor's, local connections have no map to load
+    let Some(connection) = map
+        .0
+        .get(column, row)
+        .and_then(|tile| tile.connection.as_ref())
+        .filter(|connection| connection.floor.is_none() && !connection.is_local())
+        .cloned()
+    else {
+        return;
+    };
+
+    let path = directory.0.join(&connection.map);
+    let world = match MultiFloorMap::load_from_path(&path) {
+        Ok(world) => world,
+        Err(error) => {
+            println!(
+                "Failed to follow connection to {}: {}",
+                path.display(),
+                error
+            );
+            return;
+        }
+    };
+
+    // connections lead to the first floor, stairs take it from there
+    let target = world.floors[0].clone();
+    let (x, y) = connection.spawn;
+    if !target.is_walkable(x, y) {
+        println!(
+            "Failed to follow connection to {}: spawn {:?} can't be stood on",
+            connection.map, connection.spawn
+        );
+        return;
+    }
+
+    for entity in &level {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_level(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &target,
+        &theme,
+        *wall_mode,
+        *tile_size,
+    );
+    map.0 = target;
+    map_file.0 = connection.map;
+    directory.0 = map_directory(&path);
+    floors.0 = world;
+    current_floor.0 = 0;
+
+    for mut transform in player.iter_mut() {
+        transform.translation = grid_to_world((y, x), *tile_size);
+    }
+    position.pos = (y, x);
+    *last_position = Some(position.pos);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn culled_walls_take_fewer_triangles_than_cubes() {
+        let maze = Map::from_ascii(
+            "#########\n\
+             #.#.....#\n\
+             #.#.###.#\n\
+             #...#...#\n\
+             #####.###\n\
+             #.....#.#\n\
+             #.###.#.#\n\
+             #...#...#\n\
+             #########\n",
+        )
+        .unwrap();
+
+        let culled = WallMeshMode::Culled.triangles(&maze);
+        let cubes = WallMeshMode::Cubes.triangles(&maze);
+        // 50 wall tiles, the culled mesh only keeps the faces next to the corridors
+        assert_eq!(cubes, 600);
+        assert_eq!(culled, 212);
+    }
+
+    #[test]
+    fn cube_walls_are_opt_in() {
+        assert!(WallMeshMode::from_args(&args(&["game"])) == WallMeshMode::Culled);
+        assert!(
+            WallMeshMode::from_args(&args(&["game", "--cube-walls", "level.map"]))
+                == WallMeshMode::Cubes
+        );
+        assert_eq!(
+            map_argument(&args(&["game", "--cube-walls", "level.map"])),
+            Some("level.map")
+        );
+    }
+
+    #[test]
+    fn turned_doors_span_their_tile() {
+        let tile_size = TileSize {
+            width: 2.0,
+            height: 1.0,
+        };
+
+        // the door mesh spans the tile along its local x, which ends up along z for east and west
+        for (direction, span) in [
+            (Direction::North, Vec3::new(2.0, 0.0, 0.0)),
+            (Direction::South, Vec3::new(2.0, 0.0, 0.0)),
+            (Direction::East, Vec3::new(0.0, 0.0, 1.0)),
+            (Direction::West, Vec3::new(0.0, 0.0, 1.0)),
+        ] {
+            let rotation = direction.to_quat();
+            let transform = Transform::from_rotation(rotation)
+                .with_scale(rotated_tile_scale(rotation, tile_size));
+            let spanned = transform.transform_point(Vec3::X).abs();
+
+            assert!(
+                spanned.abs_diff_eq(span, 1e-5),
+                "{:?} spans {}",
+                direction,
+                spanned
+            );
+        }
+    }
+
+    // player_movement alone on `ascii`, with the player on `start` as (row, column) and no delay between moves
+    fn movement_app(ascii: &str, start: (usize, usize)) -> App {
+        let mut app = App::new();
+        app.init_resource::<Input<KeyCode>>()
+            .init_resource::<Gamepads>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .init_resource::<Time>()
+            .init_resource::<MovementLog>()
+            .init_resource::<CurrentMapFile>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<TileSize>()
+            .insert_resource(CurrentMap(Map::from_ascii(ascii).unwrap()))
+            .insert_resource(PlayerPosition {
+                pos: start,
+                timer: Timer::from_seconds(0.0, TimerMode::Once),
+            })
+            .add_systems(Update, player_movement);
+        app.world.spawn((
+            Transform::from_translation(grid_to_world(start, TileSize::default())),
+            Player,
+            Facing(Direction::South),
+        ));
+        app
+    }
+
+    // Holds exactly `keys` for one update
+    fn press(app: &mut App, keys: &[KeyCode]) {
+        let mut input = app.world.resource_mut::<Input<KeyCode>>();
+        input.release_all();
+        for &key in keys {
+            input.press(key);
+        }
+        app.update();
+    }
+
+    #[test]
+    fn movement_stops_at_the_map_edge() {
+        let mut app = movement_app("..\n..\n", (0, 0));
+
+        // up and left both lead off the map from the top left corner
+        press(&mut app, &[KeyCode::W, KeyCode::A]);
+
+        assert_eq!(app.world.resource::<PlayerPosition>().pos, (0, 0));
+        let mut player = app.world.query_filtered::<&Transform, With<Player>>();
+        assert_eq!(
+            player.single(&app.world).translation,
+            grid_to_world((0, 0), TileSize::default())
+        );
+
+        press(&mut app, &[KeyCode::S]);
+
+        assert_eq!(app.world.resource::<PlayerPosition>().pos, (1, 0));
+    }
+
+    #[test]
+    fn facing_follows_the_last_move() {
+        let mut app = movement_app("...\n...\n...\n", (1, 1));
+        let mut facing = app.world.query_filtered::<&Facing, With<Player>>();
+
+        for (key, position, direction) in [
+            (KeyCode::D, (1, 2), Direction::East),
+            (KeyCode::W, (0, 2), Direction::North),
+            // blocked by the edge, the player keeps facing north
+            (KeyCode::W, (0, 2), Direction::North),
+            (KeyCode::A, (0, 1), Direction::West),
+        ] {
+            press(&mut app, &[key]);
+
+            assert_eq!(app.world.resource::<PlayerPosition>().pos, position);
+            assert_eq!(facing.single(&app.world).0, direction);
+        }
+
+        // standing still doesn't turn back to the default
+        press(&mut app, &[]);
+        assert_eq!(facing.single(&app.world).0, Direction::West);
+    }
+
+    #[test]
+    fn starting_tile_is_not_an_arrival() {
+        let mut last_position = None;
+
+        assert!(!arrived(&mut last_position, (1, 1)));
+        assert!(!arrived(&mut last_position, (1, 1)));
+        assert!(arrived(&mut last_position, (1, 2)));
+        assert!(!arrived(&mut last_position, (1, 2)));
+    }
+
+    #[test]
+    fn connections_resolve_next_to_their_map() {
+        let directory = map_directory(Path::new("levels/castle/hall.map"));
+        assert_eq!(
+            directory.join("cellar.map"),
+            Path::new("levels/castle/cellar.map")
+        );
+
+        // followed connections keep resolving from the map they led to
+        let next = directory.join("tower/stairs.map");
+        assert_eq!(
+            map_directory(&next).join("roof.map"),
+            Path::new("levels/castle/tower/roof.map")
+        );
+
+        assert_eq!(map_directory(Path::new("hall.map")), PathBuf::new());
+    }
+
+    #[test]
+    fn walls_block_movement_and_doors_dont() {
+        let mut app = movement_app(".D+\n...\n", (0, 0));
+
+        press(&mut app, &[KeyCode::D]);
+        assert_eq!(app.world.resource::<PlayerPosition>().pos, (0, 1));
+
+        // the wall object blocks its walkable tile
+        press(&mut app, &[KeyCode::D]);
+        assert_eq!(app.world.resource::<PlayerPosition>().pos, (0, 1));
+
+        press(&mut app, &[KeyCode::S]);
+        assert_eq!(app.world.resource::<PlayerPosition>().pos, (1, 1));
+    }
+
+    #[test]
+    fn camera_eases_towards_the_player_at_any_frame_rate() {
+        let (start, target) = (Vec3::ZERO, Vec3::new(10.0, 0.0, -4.0));
+
+        let step = follow_step(start, target, 8.0, 1.0 / 60.0);
+        assert!(step.x > 0.0 && step.x < target.x);
+
+        // two half frames get as far as one whole frame
+        let halves = follow_step(
+            follow_step(start, target, 8.0, 1.0 / 120.0),
+            target,
+            8.0,
+            1.0 / 120.0,
+        );
+        assert!(halves.abs_diff_eq(step, 1e-4));
+
+        assert_eq!(follow_step(start, target, 0.0, 1.0 / 60.0), target);
+        assert_eq!(follow_step(target, target, 8.0, 1.0 / 60.0), target);
+    }
+
+    #[test]
+    fn custom_bindings_drive_movement() {
+        let mut bindings = KeyBindings::default();
+        bindings
+            .0
+            .insert(Action::MoveUp, vec![KeyCode::Up, KeyCode::W]);
+        bindings.0.remove(&Action::Interact);
+
+        assert_eq!(bindings.keys(Action::MoveUp), [KeyCode::Up, KeyCode::W]);
+        assert_eq!(bindings.keys(Action::MoveDown), [KeyCode::S]);
+        assert!(bindings.keys(Action::Interact).is_empty());
+
+        let mut app = movement_app("..\n..\n", (1, 0));
+        app.insert_resource(bindings);
+
+        press(&mut app, &[KeyCode::Up]);
+        assert_eq!(app.world.resource::<PlayerPosition>().pos, (0, 0));
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn map_argument_skips_flags_and_their_values() {
+        assert_eq!(map_argument(&args(&["game"])), None);
+        assert_eq!(
+            map_argument(&args(&["game", "level.map"])),
+            Some("level.map")
+        );
+        assert_eq!(
+            map_argument(&args(&["game", "--record", "moves.log", "level.map"])),
+            Some("level.map")
+        );
+        assert_eq!(
+            map_argument(&args(&["game", "--replay", "moves.log"])),
+            None
+        );
+
+        assert_eq!(
+            flag_value(&args(&["game", "--record", "moves.log"]), "--record"),
+            Some("moves.log")
+        );
+        assert_eq!(flag_value(&args(&["game", "--record"]), "--record"), None);
+    }
+
+    #[test]
+    fn settings_match_the_shader_uniform() {
+        // four f32s then a vec3, which wgsl aligns to 16 bytes
+        assert_eq!(PostProcessSettings::min_size().get(), 32);
+
+        let shader = include_str!("../assets/pixel_art.wgsl");
+        assert!(shader.contains(
+            "struct PostProcessSettings {\n    \
+             height: f32,\n    \
+             width: f32,\n    \
+             pixel_size: f32,\n    \
+             enabled: f32,\n    \
+             tint: vec3<f32>,\n\
+             }"
+        ));
+    }
+
+    #[test]
+    fn f4_toggles_the_post_process_pass() {
+        let mut app = App::new();
+        app.init_resource::<Input<KeyCode>>()
+            .add_systems(Update, toggle_post_process);
+        let camera = app
+            .world
+            .spawn(PostProcessSettings {
+                enabled: 1.0,
+                ..default()
+            })
+            .id();
+        let enabled = |app: &App| {
+            app.world
+                .get::<PostProcessSettings>(camera)
+                .unwrap()
+                .is_enabled()
+        };
+
+        for expected in [false, true] {
+            let mut input = app.world.resource_mut::<Input<KeyCode>>();
+            input.reset_all();
+            input.press(KeyCode::F4);
+            app.update();
+            assert_eq!(enabled(&app), expected);
+        }
+
+        // holding the key doesn't keep toggling
+        app.world.resource_mut::<Input<KeyCode>>().clear();
+        app.update();
+        assert!(enabled(&app));
+    }
+
+    #[test]
+    fn sticks_snap_to_the_nearest_direction() {
+        // inside the deadzone, including pushes that would each be past it on one axis alone
+        assert_eq!(quantize_stick(Vec2::ZERO), None);
+        assert_eq!(quantize_stick(Vec2::new(0.3, -0.3)), None);
+        assert_eq!(quantize_stick(Vec2::new(0.0, 0.49)), None);
+
+        assert_eq!(quantize_stick(Vec2::new(0.0, 1.0)), Some(Direction::North));
+        assert_eq!(quantize_stick(Vec2::new(0.0, -1.0)), Some(Direction::South));
+        assert_eq!(quantize_stick(Vec2::new(1.0, 0.0)), Some(Direction::East));
+        assert_eq!(quantize_stick(Vec2::new(-1.0, 0.0)), Some(Direction::West));
+        assert_eq!(quantize_stick(Vec2::new(0.8, 0.3)), Some(Direction::East));
+        assert_eq!(
+            quantize_stick(Vec2::new(-0.2, -0.9)),
+            Some(Direction::South)
+        );
+
+        // exact diagonals go to the vertical direction
+        assert_eq!(quantize_stick(Vec2::new(0.6, 0.6)), Some(Direction::North));
+        assert_eq!(
+            quantize_stick(Vec2::new(-0.6, -0.6)),
+            Some(Direction::South)
+        );
+    }
+}
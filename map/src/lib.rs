@@ -1,26 +1,128 @@
 use core::fmt;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BinaryHeap, HashMap, HashSet, VecDeque},
+    f32::consts::{FRAC_PI_2, PI},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use bevy::prelude::{Component, Quat};
-use serde::{Deserialize, Serialize};
+use bevy::prelude::{Color, Component, Quat, Resource, Vec2, Vec3};
+#[cfg(feature = "image")]
+use image::{Rgba, RgbaImage};
+#[cfg(feature = "gen")]
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(
-    Serialize, Deserialize, Copy, Clone, Debug, Component, PartialEq, Eq, PartialOrd, Ord, Default,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Component,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
 )]
+#[non_exhaustive]
 pub enum TileType {
     Walkable,
     #[default]
     Blocked,
+    Water,
+    Hazard,
+}
+
+// Colors and text symbols for every type live here so adding a variant only needs updating in one place
+impl TileType {
+    pub const ALL: [TileType; 4] = [
+        TileType::Walkable,
+        TileType::Blocked,
+        TileType::Water,
+        TileType::Hazard,
+    ];
+
+    // Only plain floor can be stood on, water and hazards are open but impassable
+    pub fn is_walkable(&self) -> bool {
+        match self {
+            TileType::Walkable => true,
+            TileType::Blocked | TileType::Water | TileType::Hazard => false,
+        }
+    }
+
+    pub fn editor_color(&self) -> Color {
+        match self {
+            TileType::Walkable => Color::WHITE,
+            TileType::Blocked => Color::GRAY,
+            TileType::Water => Color::rgb(0.25, 0.45, 0.9),
+            TileType::Hazard => Color::rgb(0.9, 0.35, 0.1),
+        }
+    }
+
+    pub fn game_color(&self) -> Color {
+        match self {
+            TileType::Walkable => Color::rgb(1.0, 1.0, 1.0),
+            TileType::Blocked => Color::rgb(0.5, 0.5, 0.5),
+            TileType::Water => Color::rgb(0.15, 0.35, 0.8),
+            TileType::Hazard => Color::rgb(0.8, 0.25, 0.05),
+        }
+    }
+
+    // Character the ascii and run-length formats use for the type
+    pub fn symbol(&self) -> char {
+        match self {
+            TileType::Walkable => '.',
+            TileType::Blocked => '#',
+            TileType::Water => '~',
+            TileType::Hazard => '^',
+        }
+    }
+
+    pub fn from_symbol(symbol: char) -> Option<TileType> {
+        TileType::ALL
+            .into_iter()
+            .find(|tile_type| tile_type.symbol() == symbol)
+    }
 }
 
 #[derive(
-    Serialize, Deserialize, Copy, Clone, Debug, Component, PartialEq, Eq, PartialOrd, Ord, Default,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Component,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
 )]
+#[non_exhaustive]
 pub enum ObjectType {
     #[default]
     Wall,
     Door,
 }
 
+impl ObjectType {
+    pub const ALL: [ObjectType; 2] = [ObjectType::Wall, ObjectType::Door];
+
+    pub fn game_color(&self) -> Color {
+        match self {
+            ObjectType::Wall => Color::rgb(0.5, 0.5, 0.5),
+            ObjectType::Door => Color::rgb(0.55, 0.35, 0.2),
+        }
+    }
+}
+
 impl fmt::Display for ObjectType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -33,144 +135,3240 @@ impl fmt::Display for ObjectType {
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, Component, PartialEq, Default)]
 pub struct Object {
     pub object_type: ObjectType,
+    #[serde(deserialize_with = "deserialize_rotation")]
     pub rotation: Quat,
 }
 
+impl Object {
+    pub fn new(object_type: ObjectType, rotation: Quat) -> Self {
+        Self {
+            object_type,
+            rotation: normalize_rotation(rotation),
+        }
+    }
+
+    pub fn facing(object_type: ObjectType, direction: Direction) -> Self {
+        Self::new(object_type, direction.to_quat())
+    }
+}
+
+// Hand edited or float drifted files can hold quaternions that would skew the mesh.
+// Rotations that are already unit length are kept bit for bit so saving and loading doesn't drift them
+fn normalize_rotation(rotation: Quat) -> Quat {
+    if rotation.is_normalized() {
+        rotation
+    } else if rotation.is_finite() && rotation.length_squared() > f32::EPSILON {
+        rotation.normalize()
+    } else {
+        Quat::IDENTITY
+    }
+}
+
+// Quarter turns about +y come back exactly as Object::new stores them, so repeated rotating and mirroring
+// doesn't drift or flip the sign of the quaternion
+fn snap_rotation(rotation: Quat) -> Quat {
+    Direction::from_quat(rotation)
+        .map(Direction::to_quat)
+        .unwrap_or_else(|| normalize_rotation(rotation))
+}
+
+fn deserialize_rotation<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Quat, D::Error> {
+    Quat::deserialize(deserializer).map(normalize_rotation)
+}
+
+// Hashes what PartialEq compares, so 0.0 and -0.0 hash the same and every NaN hashes alike
+impl Hash for Object {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.object_type.hash(state);
+        for component in self.rotation.to_array() {
+            let component = if component.is_nan() {
+                f32::NAN
+            } else {
+                component + 0.0
+            };
+            component.to_bits().hash(state);
+        }
+    }
+}
+
 #[derive(
-    Serialize, Deserialize, Copy, Clone, Debug, Component, PartialEq, Eq, PartialOrd, Ord, Default,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Component,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
 )]
 pub struct FloorObject {
     pub object_type: ObjectType,
 }
 
 #[derive(
-    Serialize, Deserialize, Clone, Debug, Component, PartialEq, Eq, PartialOrd, Ord, Default,
+    Serialize, Deserialize, Clone, Debug, Component, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
 )]
 pub struct Connection {
     pub map: String,
     pub spawn: (usize, usize),
+    // set on stairs, the index of the floor they lead to within a MultiFloorMap
+    #[serde(default)]
+    pub floor: Option<usize>,
+}
+
+impl Connection {
+    // A connection with no map name that isn't stairs links to a spawn within the map holding it
+    pub fn is_local(&self) -> bool {
+        self.map.is_empty() && self.floor.is_none()
+    }
 }
 
 impl fmt::Display for Connection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Map: {}, Spawn: {:?}", self.map, self.spawn)
+        match self.floor {
+            Some(floor) => write!(f, "Floor: {}, Spawn: {:?}", floor, self.spawn),
+            None => write!(f, "Map: {}, Spawn: {:?}", self.map, self.spawn),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::North,
+        Direction::East,
+        Direction::South,
+        Direction::West,
+    ];
+
+    // Offset in (x, y) grid coordinates, north points towards the first row
+    pub fn offset(&self) -> (i32, i32) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::East => (1, 0),
+            Direction::South => (0, 1),
+            Direction::West => (-1, 0),
+        }
+    }
+
+    // Yaw about +y that turns an object's forward, -z in the game, to face this way.
+    // Rows run along +z so north is -z and east is +x
+    pub fn to_quat(self) -> Quat {
+        let turns = match self {
+            Direction::North => 0.0,
+            Direction::West => 1.0,
+            Direction::South => 2.0,
+            Direction::East => 3.0,
+        };
+        normalize_rotation(Quat::from_rotation_y(turns * FRAC_PI_2))
+    }
+
+    // The direction a rotation faces, None unless it's one of the four quarter turns
+    pub fn from_quat(rotation: Quat) -> Option<Direction> {
+        Direction::ALL
+            .into_iter()
+            .find(|direction| direction.to_quat().dot(rotation).abs() > 1.0 - 1e-5)
+    }
+
+    // A quarter turn clockwise seen from above, north becomes east
+    pub fn clockwise(self) -> Direction {
+        match self {
+            Direction::North => Direction::East,
+            Direction::East => Direction::South,
+            Direction::South => Direction::West,
+            Direction::West => Direction::North,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Component, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Component, PartialEq, Hash, Default)]
 pub struct Tile {
     pub tile_type: TileType,
     pub object: Option<Object>,
     pub floor_object: Option<FloorObject>,
     pub connection: Option<Connection>,
+    // more objects stacked on the tile, a torch on a wall or a rug under a door
+    #[serde(default)]
+    pub decorations: Vec<Object>,
+}
+
+impl Tile {
+    pub fn walkable() -> Self {
+        Self {
+            tile_type: TileType::Walkable,
+            ..Default::default()
+        }
+    }
+
+    pub fn blocked() -> Self {
+        Self {
+            tile_type: TileType::Blocked,
+            ..Default::default()
+        }
+    }
+
+    // Back to a plain blocked tile with no object, floor object or connection
+    pub fn clear(&mut self) {
+        *self = Tile::blocked();
+    }
+
+    pub fn with_object(mut self, object_type: ObjectType, rotation: Quat) -> Self {
+        self.object = Some(Object::new(object_type, rotation));
+        self
+    }
+
+    pub fn with_floor_object(mut self, object_type: ObjectType) -> Self {
+        self.floor_object = Some(FloorObject { object_type });
+        self
+    }
+
+    pub fn with_connection(mut self, map: impl Into<String>, spawn: (usize, usize)) -> Self {
+        self.connection = Some(Connection {
+            map: map.into(),
+            spawn,
+            floor: None,
+        });
+        self
+    }
+
+    // Stairs leading to (x, y) on floor `floor` of the same MultiFloorMap
+    pub fn with_stairs(mut self, floor: usize, spawn: (usize, usize)) -> Self {
+        self.connection = Some(Connection {
+            map: String::new(),
+            spawn,
+            floor: Some(floor),
+        });
+        self
+    }
+
+    // Fills `object` first and stacks anything after that as a decoration
+    pub fn add_object(&mut self, object: Object) {
+        match self.object {
+            None => self.object = Some(object),
+            Some(_) => self.decorations.push(object),
+        }
+    }
+
+    // The object followed by the decorations
+    pub fn objects(&self) -> impl Iterator<Item = &Object> {
+        self.object.iter().chain(&self.decorations)
+    }
+
+    fn objects_mut(&mut self) -> impl Iterator<Item = &mut Object> {
+        self.object.iter_mut().chain(&mut self.decorations)
+    }
+
+    // Whether something can stand on the tile, a wall object on any layer blocks it while a door doesn't
+    pub fn is_passable(&self) -> bool {
+        self.tile_type.is_walkable() && !self.is_wall()
+    }
+
+    // Blocked tiles are implicit walls, walkable tiles can still hold an explicit wall object on any layer
+    pub fn is_wall(&self) -> bool {
+        self.tile_type == TileType::Blocked
+            || self
+                .objects()
+                .any(|object| object.object_type == ObjectType::Wall)
+    }
+}
+
+// Largest number of rows or columns a map may grow to by default
+pub const DEFAULT_MAX_DIMENSION: usize = 1024;
+
+#[cfg(feature = "gen")]
+const MIN_ROOM_SIZE: usize = 3;
+#[cfg(feature = "gen")]
+const MAX_ROOM_SIZE: usize = 8;
+#[cfg(feature = "gen")]
+const ROOM_ATTEMPTS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExpandError {
+    pub width: i64,
+    pub height: i64,
+    pub max_dimension: usize,
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expanding to {}x{} would exceed the maximum map dimension of {}",
+            self.width, self.height, self.max_dimension
+        )
+    }
+}
+
+impl std::error::Error for ExpandError {}
+
+// Summary from Map::tile_counts, doors, walls and connections are counted on top of the tile types and
+// doors and walls count every layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct TileCounts {
+    pub walkable: usize,
+    pub blocked: usize,
+    pub water: usize,
+    pub hazards: usize,
+    pub doors: usize,
+    pub walls: usize,
+    pub connections: usize,
+}
+
+impl fmt::Display for TileCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} walkable, {} blocked, {} water, {} hazards, {} doors, {} walls, {} connections",
+            self.walkable,
+            self.blocked,
+            self.water,
+            self.hazards,
+            self.doors,
+            self.walls,
+            self.connections
+        )
+    }
+}
+
+// How Map::stamp treats the tiles of the stamped map
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StampMode {
+    // every tile replaces the one under it
+    #[default]
+    Overwrite,
+    // plain blocked tiles, with no object, floor object or connection, leave the tile under them alone
+    // so a room's margin doesn't erase what it's stamped onto
+    SkipBlocked,
+}
+
+// Why Map::from_ascii rejected its input, lines and columns count from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCharacter {
+        line: usize,
+        column: usize,
+        character: char,
+    },
+    Ragged {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    // a run-length pair that isn't `count:symbol`
+    InvalidRun {
+        line: usize,
+        run: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownCharacter {
+                line,
+                column,
+                character,
+            } => write!(
+                f,
+                "Unknown tile {:?} at line {}, column {}",
+                character, line, column
+            ),
+            ParseError::Ragged {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Line {} is {} tiles wide but the first line is {}",
+                line, found, expected
+            ),
+            ParseError::InvalidRun { line, run } => {
+                write!(f, "Invalid run {:?} at line {}", run, line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapFormat {
+    Json,
+    // bincode after BINARY_MAGIC, smaller and faster to load than json for big maps
+    Binary,
+}
+
+const BINARY_MAGIC: &[u8] = b"MAPB";
+
+impl MapFormat {
+    // Guesses the format from the start of the file contents
+    pub fn sniff(bytes: &[u8]) -> Option<MapFormat> {
+        if bytes.starts_with(BINARY_MAGIC) {
+            return Some(MapFormat::Binary);
+        }
+
+        match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => Some(MapFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<MapFormat> {
+        match extension {
+            "map" | "json" => Some(MapFormat::Json),
+            "mapb" => Some(MapFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(std::io::Error),
+    Write(std::io::Error),
+    Parse(String),
+    Validation(String),
+    UnknownFormat,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Io(error) => write!(f, "Failed to read map: {}", error),
+            MapError::Write(error) => write!(f, "Failed to write map: {}", error),
+            MapError::Parse(error) => write!(f, "Failed to parse map: {}", error),
+            MapError::Validation(error) => write!(f, "Invalid map: {}", error),
+            MapError::UnknownFormat => write!(f, "Unrecognized map format"),
+        }
+    }
+}
+
+impl std::error::Error for MapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MapError::Io(error) | MapError::Write(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MapError {
+    fn from(error: std::io::Error) -> Self {
+        MapError::Io(error)
+    }
+}
+
+// A connection at `at` whose target can't be used, positions are (x, y)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionError {
+    MissingMap {
+        at: (usize, usize),
+        map: String,
+    },
+    SpawnOutOfBounds {
+        at: (usize, usize),
+        map: String,
+        spawn: (usize, usize),
+    },
+    SpawnBlocked {
+        at: (usize, usize),
+        map: String,
+        spawn: (usize, usize),
+    },
+}
+
+impl ConnectionError {
+    // The tile holding the broken connection
+    pub fn at(&self) -> (usize, usize) {
+        match self {
+            ConnectionError::MissingMap { at, .. }
+            | ConnectionError::SpawnOutOfBounds { at, .. }
+            | ConnectionError::SpawnBlocked { at, .. } => *at,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::MissingMap { at, map } => {
+                write!(f, "Connection at {:?} leads to missing map {}", at, map)
+            }
+            ConnectionError::SpawnOutOfBounds { at, map, spawn } => write!(
+                f,
+                "Connection at {:?} spawns at {:?} outside of {}",
+                at, spawn, map
+            ),
+            ConnectionError::SpawnBlocked { at, map, spawn } => write!(
+                f,
+                "Connection at {:?} spawns on a blocked tile {:?} in {}",
+                at, spawn, map
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+// Where Map::save_to_path keeps the previous version of `path`, e.g. level.map.bak
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+// Colors the editor and game draw tiles and objects with, types missing from a theme file use their built in color
+#[derive(Serialize, Deserialize, Clone, Debug, Resource, PartialEq)]
+#[serde(default)]
+pub struct Theme {
+    pub tiles: HashMap<TileType, [f32; 3]>,
+    pub objects: HashMap<ObjectType, [f32; 3]>,
+    // floor color of tile (0, 0) in the game, None draws it like any other floor
+    pub origin_marker: Option<[f32; 3]>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            tiles: TileType::ALL
+                .into_iter()
+                .map(|tile_type| (tile_type, rgb(tile_type.game_color())))
+                .collect(),
+            objects: ObjectType::ALL
+                .into_iter()
+                .map(|object_type| (object_type, rgb(object_type.game_color())))
+                .collect(),
+            origin_marker: Some([0.0, 0.0, 0.0]),
+        }
+    }
+}
+
+impl Theme {
+    pub fn load_from_path(path: impl AsRef<Path>) -> Result<Theme, MapError> {
+        let contents = std::fs::read(path)?;
+        serde_json::from_slice(&contents).map_err(|error| MapError::Parse(error.to_string()))
+    }
+
+    pub fn tile_color(&self, tile_type: TileType) -> Color {
+        self.tiles
+            .get(&tile_type)
+            .map(|&[r, g, b]| Color::rgb(r, g, b))
+            .unwrap_or_else(|| tile_type.game_color())
+    }
+
+    pub fn object_color(&self, object_type: ObjectType) -> Color {
+        self.objects
+            .get(&object_type)
+            .map(|&[r, g, b]| Color::rgb(r, g, b))
+            .unwrap_or_else(|| object_type.game_color())
+    }
+
+    pub fn origin_marker(&self) -> Option<Color> {
+        self.origin_marker.map(|[r, g, b]| Color::rgb(r, g, b))
+    }
+}
+
+fn rgb(color: Color) -> [f32; 3] {
+    [color.r(), color.g(), color.b()]
+}
+
+// World size of one tile, tile (x, y) is centered on (x * width, y * height)
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct TileSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for TileSize {
+    fn default() -> Self {
+        Self::square(1.0)
+    }
+}
+
+impl TileSize {
+    pub fn square(size: f32) -> Self {
+        Self {
+            width: size,
+            height: size,
+        }
+    }
+
+    pub fn as_vec2(&self) -> Vec2 {
+        Vec2::new(self.width, self.height)
+    }
+
+    // Center of tile (x, y)
+    pub fn tile_to_world(&self, x: i32, y: i32) -> Vec2 {
+        Vec2::new(x as f32 * self.width, y as f32 * self.height)
+    }
+
+    // The tile covering `position`, which can be outside of any map
+    pub fn world_to_tile(&self, position: Vec2) -> (i32, i32) {
+        (
+            (position.x / self.width + 0.5).floor() as i32,
+            (position.y / self.height + 0.5).floor() as i32,
+        )
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Component, PartialEq, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Component, PartialEq, Hash, Default)]
 pub struct Map {
     pub tiles: Vec<Vec<Tile>>,
+    // (x, y) the player starts on, Map::default_spawn picks a tile when it's unset
+    #[serde(default)]
+    pub spawn: Option<(usize, usize)>,
 }
 
 impl Map {
-    pub fn expand_to(&mut self, y: i32, x: i32) {
-        if (self.tiles.len() as i32) < x + 1 {
-            self.tiles.resize(
-                (x + 1).try_into().unwrap(),
-                vec![Tile::default(); self.tiles.get(0).unwrap_or(&vec![]).len()],
-            );
-        }
-        for row in self.tiles.iter_mut() {
-            if (row.len() as i32) < y + 1 {
-                row.resize((y + 1).try_into().unwrap(), Tile::default());
-            }
+    // A width x height map with every tile blocked
+    pub fn new_blocked(width: usize, height: usize) -> Map {
+        Map {
+            tiles: vec![vec![Tile::blocked(); width]; height],
+            spawn: None,
         }
+    }
 
-        if 0 > x + 1 {
-            for _ in (x + 1)..0 {
-                self.tiles.insert(
-                    0,
-                    vec![Tile::default(); self.tiles.get(0).unwrap_or(&vec![]).len()],
-                )
-            }
+    // (width, height) where width is the longest row, so ragged maps are covered too
+    pub fn dimensions(&self) -> (usize, usize) {
+        let width = self.tiles.iter().map(|row| row.len()).max().unwrap_or(0);
+        (width, self.tiles.len())
+    }
+
+    // True when the map has no rows or only zero-width rows
+    pub fn is_empty(&self) -> bool {
+        self.tiles.iter().all(|row| row.is_empty())
+    }
+
+    // A maze carved with a recursive backtracker, every walkable tile is reachable from every other
+    #[cfg(feature = "gen")]
+    pub fn generate_maze(width: usize, height: usize, seed: u64) -> Map {
+        let mut map = Map::new_blocked(width, height);
+        if width < 3 || height < 3 {
+            return map;
         }
-        for row in self.tiles.iter_mut() {
-            if 0 > y + 1 {
-                for _ in (y + 1)..0 {
-                    row.insert(0, Tile::default());
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut stack: Vec<(usize, usize)> = vec![(1, 1)];
+        map.tiles[1][1] = Tile::walkable();
+
+        // cells sit on odd coordinates with the tile between two cells as the passage
+        while let Some(&(x, y)) = stack.last() {
+            let unvisited: Vec<(usize, usize)> = Direction::ALL
+                .iter()
+                .filter_map(|direction| {
+                    let (dx, dy) = direction.offset();
+                    let x = x.checked_add_signed(dx as isize * 2)?;
+                    let y = y.checked_add_signed(dy as isize * 2)?;
+                    (x < width - 1
+                        && y < height - 1
+                        && map.tiles[y][x].tile_type == TileType::Blocked)
+                        .then_some((x, y))
+                })
+                .collect();
+
+            match unvisited.choose(&mut rng) {
+                Some(&(next_x, next_y)) => {
+                    map.tiles[(y + next_y) / 2][(x + next_x) / 2] = Tile::walkable();
+                    map.tiles[next_y][next_x] = Tile::walkable();
+                    stack.push((next_x, next_y));
+                }
+                None => {
+                    stack.pop();
                 }
             }
         }
+
+        map
     }
 
-    pub fn trim(&mut self) {
-        let horizontal_lower_bound = self
-            .tiles
-            .iter()
-            .enumerate()
-            .find(|(_, row)| row.iter().any(|tile| tile.tile_type != TileType::Blocked))
-            .map(|(i, _)| i)
-            .unwrap_or(0);
-        let horizontal_upper_bound = self
-            .tiles
-            .iter()
-            .enumerate()
-            .rev()
-            .find(|(_, row)| row.iter().any(|tile| tile.tile_type != TileType::Blocked))
-            .map(|(i, _)| i)
-            .unwrap_or(self.tiles.len() - 1);
+    // Randomly placed rooms, each joined to the previous one by a corridor so they form a single region
+    #[cfg(feature = "gen")]
+    pub fn generate_rooms(width: usize, height: usize, seed: u64) -> Map {
+        let mut map = Map::new_blocked(width, height);
+        if width < 3 || height < 3 {
+            return map;
+        }
 
-        let vertical_lower_bound = self
-            .tiles
-            .iter()
-            .map(|row| {
-                row.iter()
-                    .enumerate()
-                    .find(|(_, tile)| tile.tile_type != TileType::Blocked)
-                    .map(|(i, _)| i)
-                    .unwrap_or(0)
-            })
-            .min()
-            .unwrap_or(0);
-        let vertical_upper_bound = self
-            .tiles
-            .iter()
-            .map(|row| {
-                row.iter()
-                    .enumerate()
-                    .rev()
-                    .find(|(_, tile)| tile.tile_type != TileType::Blocked)
-                    .map(|(i, _)| i)
-                    .unwrap_or(row.len() - 1)
-            })
-            .max()
-            .unwrap_or(self.tiles.get(0).map(|row| row.len() - 1).unwrap_or(0));
+        let mut rng = StdRng::seed_from_u64(seed);
+        let max_width = MAX_ROOM_SIZE.min(width - 2);
+        let max_height = MAX_ROOM_SIZE.min(height - 2);
+        // (x, y, width, height)
+        let mut rooms: Vec<(usize, usize, usize, usize)> = Vec::new();
 
-        self.tiles = self
-            .tiles
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| *i >= horizontal_lower_bound && *i <= horizontal_upper_bound)
-            .map(|(_, row)| {
-                row.iter()
-                    .enumerate()
-                    .filter(|(i, _)| *i >= vertical_lower_bound && *i <= vertical_upper_bound)
-                    .map(|(_, tile)| tile.clone())
-                    .collect()
-            })
-            .collect();
-    }
+        for _ in 0..ROOM_ATTEMPTS {
+            let room_width = rng.gen_range(MIN_ROOM_SIZE.min(max_width)..=max_width);
+            let room_height = rng.gen_range(MIN_ROOM_SIZE.min(max_height)..=max_height);
+            let x = rng.gen_range(1..=width - 1 - room_width);
+            let y = rng.gen_range(1..=height - 1 - room_height);
 
-    pub fn pad(&mut self, padding: usize) {
-        let length = self.tiles.len();
-        let width = self.tiles.get(0).map(|row| row.len()).unwrap_or(0);
+            // keep at least one blocked tile between rooms
+            let overlaps = rooms
+                .iter()
+                .any(|&(other_x, other_y, other_width, other_height)| {
+                    x <= other_x + other_width
+                        && other_x <= x + room_width
+                        && y <= other_y + other_height
+                        && other_y <= y + room_height
+                });
+            if overlaps {
+                continue;
+            }
 
-        let mut new_tiles = vec![vec![Tile::default(); width + padding * 2]; length + padding * 2];
+            for row in &mut map.tiles[y..y + room_height] {
+                for tile in &mut row[x..x + room_width] {
+                    *tile = Tile::walkable();
+                }
+            }
 
-        for (i, row) in self.tiles.iter().enumerate() {
-            for (j, tile) in row.iter().enumerate() {
-                new_tiles[i + padding][j + padding] = tile.clone();
+            let center = (x + room_width / 2, y + room_height / 2);
+            if let Some(&(other_x, other_y, other_width, other_height)) = rooms.last() {
+                let other_center = (other_x + other_width / 2, other_y + other_height / 2);
+                // the corner of the L shaped corridor
+                let corner = if rng.gen_bool(0.5) {
+                    (center.0, other_center.1)
+                } else {
+                    (other_center.0, center.1)
+                };
+
+                for (from, to) in [(other_center, corner), (corner, center)] {
+                    for y in from.1.min(to.1)..=from.1.max(to.1) {
+                        for x in from.0.min(to.0)..=from.0.max(to.0) {
+                            map.tiles[y][x] = Tile::walkable();
+                        }
+                    }
+                }
             }
+
+            rooms.push((x, y, room_width, room_height));
         }
 
-        self.tiles = new_tiles;
+        map
+    }
+
+    pub fn decode(bytes: &[u8], format: MapFormat) -> Result<Map, MapError> {
+        let map: Map = match format {
+            MapFormat::Json => {
+                serde_json::from_slice(bytes).map_err(|error| MapError::Parse(error.to_string()))?
+            }
+            MapFormat::Binary => {
+                let bytes = bytes
+                    .strip_prefix(BINARY_MAGIC)
+                    .ok_or_else(|| MapError::Parse("missing binary map header".to_string()))?;
+                bincode::deserialize(bytes).map_err(|error| MapError::Parse(error.to_string()))?
+            }
+        };
+
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn encode(&self, format: MapFormat) -> Result<Vec<u8>, MapError> {
+        match format {
+            MapFormat::Json => Ok(serde_json::to_vec(self).map_err(std::io::Error::from)?),
+            MapFormat::Binary => Ok(self.to_bytes()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        // bincode only fails on sequences without a known length, which a map never has
+        bytes.extend(bincode::serialize(self).expect("maps always serialize"));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Map, MapError> {
+        Map::decode(bytes, MapFormat::Binary)
+    }
+
+    // Every row has to be the same length for the map to be usable
+    pub fn validate(&self) -> Result<(), MapError> {
+        let width = self.tiles.first().map(|row| row.len()).unwrap_or(0);
+
+        match self.tiles.iter().position(|row| row.len() != width) {
+            Some(y) => Err(MapError::Validation(format!(
+                "row {} has {} tiles but the first row has {}",
+                y,
+                self.tiles[y].len(),
+                width
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn load_detect(bytes: &[u8]) -> Result<Map, MapError> {
+        let format = MapFormat::sniff(bytes).ok_or(MapError::UnknownFormat)?;
+        Map::decode(bytes, format)
+    }
+
+    // Trusts the contents of the file over its extension, which is only used when sniffing fails
+    pub fn load_from_path(path: impl AsRef<Path>) -> Result<Map, MapError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+
+        let format = MapFormat::sniff(&bytes)
+            .or_else(|| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .and_then(MapFormat::from_extension)
+            })
+            .ok_or(MapError::UnknownFormat)?;
+
+        Map::decode(&bytes, format)
+    }
+
+    // Writes to a temporary file and renames it over `path` so a crash mid write can't leave a half written map.
+    // The version being replaced is kept at backup_path(path). A .mapb extension saves as binary, anything else as json
+    pub fn save_to_path(&self, path: impl AsRef<Path>) -> Result<(), MapError> {
+        let path = path.as_ref();
+        let format = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(MapFormat::from_extension)
+            .unwrap_or(MapFormat::Json);
+        let contents = self.encode(format)?;
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let replace = || -> std::io::Result<()> {
+            let mut file = File::create(&temporary)?;
+            file.write_all(&contents)?;
+            file.sync_all()?;
+
+            if path.exists() {
+                std::fs::copy(path, backup_path(path))?;
+            }
+            std::fs::rename(&temporary, path)
+        };
+
+        replace().map_err(|error| {
+            // the temporary file is only useful until the rename, whatever went wrong
+            let _ = std::fs::remove_file(&temporary);
+            MapError::Write(error)
+        })
+    }
+
+    // Loads `path`, falling back to its backup when the file itself can't be read or parsed.
+    // The flag is true when the backup was used
+    pub fn load_or_backup(path: impl AsRef<Path>) -> Result<(Map, bool), MapError> {
+        let path = path.as_ref();
+
+        match Map::load_from_path(path) {
+            Ok(map) => Ok((map, false)),
+            Err(error) => match Map::load_from_path(backup_path(path)) {
+                Ok(map) => Ok((map, true)),
+                Err(_) => Err(error),
+            },
+        }
+    }
+
+    // Hash of the whole grid, types, objects, floor objects and connections. Equal maps always hash the same
+    // and the value is stable across runs of the same build. Lengths and enum discriminants are hashed at
+    // their native width, so it isn't meant to be stored
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.tiles.hash(&mut hasher);
+        // only mixed in when set so maps without a spawn keep their hash
+        if let Some(spawn) = self.spawn {
+            spawn.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    // Where the player starts, the spawn if it can be stood on and otherwise the first walkable tile in
+    // row-major order
+    pub fn default_spawn(&self) -> Option<(usize, usize)> {
+        self.spawn
+            .filter(|&(x, y)| self.is_walkable(x, y))
+            .or_else(|| {
+                self.tiles.iter().enumerate().find_map(|(y, row)| {
+                    (0..row.len())
+                        .find(|&x| self.is_walkable(x, y))
+                        .map(|x| (x, y))
+                })
+            })
+    }
+
+    // The tile at (x, y), None outside the map
+    pub fn get(&self, x: usize, y: usize) -> Option<&Tile> {
+        self.tiles.get(y)?.get(x)
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Tile> {
+        self.tiles.get_mut(y)?.get_mut(x)
+    }
+
+    // Whether the player can stand on (x, y), walls placed on walkable tiles still block
+    pub fn is_walkable(&self, x: usize, y: usize) -> bool {
+        self.get(x, y).is_some_and(Tile::is_passable)
+    }
+
+    // The tile next to (x, y) in `direction`, if it's inside the map
+    pub fn neighbor(
+        &self,
+        x: usize,
+        y: usize,
+        direction: Direction,
+    ) -> Option<((usize, usize), &Tile)> {
+        let (dx, dy) = direction.offset();
+        let x = x.checked_add_signed(dx as isize)?;
+        let y = y.checked_add_signed(dy as isize)?;
+        Some(((x, y), self.get(x, y)?))
+    }
+
+    // The in-bounds orthogonal neighbors of (x, y)
+    pub fn neighbors(
+        &self,
+        x: usize,
+        y: usize,
+    ) -> impl Iterator<Item = (Direction, (usize, usize), &Tile)> + '_ {
+        Direction::ALL.into_iter().filter_map(move |direction| {
+            self.neighbor(x, y, direction)
+                .map(|(position, tile)| (direction, position, tile))
+        })
+    }
+
+    // The orthogonal neighbors of (x, y) the player can stand on
+    pub fn walkable_neighbors(
+        &self,
+        x: usize,
+        y: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.neighbors(x, y)
+            .map(|(_, position, _)| position)
+            .filter(|&(x, y)| self.is_walkable(x, y))
+    }
+
+    // Like walkable_neighbors but including the four diagonals, clockwise from north
+    pub fn neighbors_8(&self, x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        [
+            (0, -1),
+            (1, -1),
+            (1, 0),
+            (1, 1),
+            (0, 1),
+            (-1, 1),
+            (-1, 0),
+            (-1, -1),
+        ]
+        .into_iter()
+        .filter_map(move |(dx, dy)| Some((x.checked_add_signed(dx)?, y.checked_add_signed(dy)?)))
+        .filter(|&(x, y)| self.is_walkable(x, y))
+    }
+
+    // Every edge of a walkable tile that faces a blocked tile or the edge of the map
+    pub fn walkable_perimeter(&self) -> Vec<((usize, usize), Direction)> {
+        let mut edges = Vec::new();
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if !tile.tile_type.is_walkable() {
+                    continue;
+                }
+
+                for direction in Direction::ALL {
+                    let open = self
+                        .neighbor(x, y, direction)
+                        .is_some_and(|(_, neighbor)| neighbor.tile_type.is_walkable());
+                    if !open {
+                        edges.push(((x, y), direction));
+                    }
+                }
+            }
+        }
+
+        edges
+    }
+
+    // Checks every connection against the map `resolver` loads for its name, errors come in row-major order of `at`.
+    // Local connections are checked against this map, stairs within a MultiFloorMap are left to MultiFloorMap::take_stairs.
+    // Each name is only resolved once however many connections lead there
+    pub fn validate_connections(
+        &self,
+        resolver: impl Fn(&str) -> Option<Map>,
+    ) -> Vec<ConnectionError> {
+        let mut errors = Vec::new();
+        let mut resolved: HashMap<&str, Option<Map>> = HashMap::new();
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let Some(connection) = &tile.connection else {
+                    continue;
+                };
+                if connection.floor.is_some() {
+                    continue;
+                }
+
+                let at = (x, y);
+                let map = connection.map.clone();
+                let spawn = connection.spawn;
+                let target = if connection.is_local() {
+                    Some(self)
+                } else {
+                    resolved
+                        .entry(&connection.map)
+                        .or_insert_with(|| resolver(&connection.map))
+                        .as_ref()
+                };
+                let Some(target) = target else {
+                    errors.push(ConnectionError::MissingMap { at, map });
+                    continue;
+                };
+
+                if target.get(spawn.0, spawn.1).is_none() {
+                    errors.push(ConnectionError::SpawnOutOfBounds { at, map, spawn });
+                } else if !target.is_walkable(spawn.0, spawn.1) {
+                    errors.push(ConnectionError::SpawnBlocked { at, map, spawn });
+                }
+            }
+        }
+
+        errors
+    }
+
+    // Counts everything in one pass, walls only counts wall objects and not blocked tiles
+    pub fn tile_counts(&self) -> TileCounts {
+        let mut counts = TileCounts::default();
+
+        for tile in self.tiles.iter().flatten() {
+            match tile.tile_type {
+                TileType::Walkable => counts.walkable += 1,
+                TileType::Blocked => counts.blocked += 1,
+                TileType::Water => counts.water += 1,
+                TileType::Hazard => counts.hazards += 1,
+            }
+            for object in tile.objects() {
+                match object.object_type {
+                    ObjectType::Door => counts.doors += 1,
+                    ObjectType::Wall => counts.walls += 1,
+                }
+            }
+            if tile.connection.is_some() {
+                counts.connections += 1;
+            }
+        }
+
+        counts
+    }
+
+    // Every (x, y) holding `tile_type`, in row-major order
+    pub fn tiles_of_type(&self, tile_type: TileType) -> Vec<(usize, usize)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, tile)| tile.tile_type == tile_type)
+                    .map(move |(x, _)| (x, y))
+            })
+            .collect()
+    }
+
+    // Sets the type of each (x, y) in `coords`, skipping any that are out of bounds
+    pub fn set_type_at(&mut self, coords: &[(usize, usize)], tile_type: TileType) {
+        for &(x, y) in coords {
+            if let Some(tile) = self.get_mut(x, y) {
+                tile.tile_type = tile_type;
+            }
+        }
+    }
+
+    // Sets the type of every tile in the rectangle between two opposite corners, inclusive and in either order.
+    // The map grows right and down to cover the rectangle first, leaving it untouched if that would pass
+    // DEFAULT_MAX_DIMENSION
+    pub fn fill_rect(
+        &mut self,
+        top_left: (usize, usize),
+        bottom_right: (usize, usize),
+        tile_type: TileType,
+    ) -> Result<(), ExpandError> {
+        let (min_x, max_x) = (
+            top_left.0.min(bottom_right.0),
+            top_left.0.max(bottom_right.0),
+        );
+        let (min_y, max_y) = (
+            top_left.1.min(bottom_right.1),
+            top_left.1.max(bottom_right.1),
+        );
+
+        self.expand_to_cover(max_x, max_y)?;
+
+        for row in &mut self.tiles[min_y..=max_y] {
+            for tile in &mut row[min_x..=max_x] {
+                tile.tile_type = tile_type;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sets the type of an 8-connected Bresenham line between `from` and `to`, both ends included.
+    // Grows the map like fill_rect when an end is outside it
+    pub fn draw_line(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        tile_type: TileType,
+    ) -> Result<(), ExpandError> {
+        self.expand_to_cover(from.0.max(to.0), from.1.max(to.1))?;
+
+        // both ends fit in the map now, so they fit in an i32
+        for (x, y) in line_points((from.0 as i32, from.1 as i32), (to.0 as i32, to.1 as i32)) {
+            self.tiles[y as usize][x as usize].tile_type = tile_type;
+        }
+
+        Ok(())
+    }
+
+    // Copies `other` onto this map with its (0, 0) at `at`, growing the map like fill_rect to fit it.
+    // Local connections in `other` are moved along so they keep pointing into the stamped tiles
+    pub fn stamp(
+        &mut self,
+        other: &Map,
+        at: (usize, usize),
+        mode: StampMode,
+    ) -> Result<(), ExpandError> {
+        let (width, height) = other.dimensions();
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        self.expand_to_cover(
+            at.0.saturating_add(width - 1),
+            at.1.saturating_add(height - 1),
+        )?;
+
+        for (y, row) in other.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if mode == StampMode::SkipBlocked && *tile == Tile::blocked() {
+                    continue;
+                }
+
+                let mut tile = tile.clone();
+                if let Some(connection) = tile.connection.as_mut().filter(|c| c.is_local()) {
+                    connection.spawn = (connection.spawn.0 + at.0, connection.spawn.1 + at.1);
+                }
+                self.tiles[at.1 + y][at.0 + x] = tile;
+            }
+        }
+
+        Ok(())
+    }
+
+    // expand_to for coordinates that can't be negative
+    fn expand_to_cover(&mut self, x: usize, y: usize) -> Result<(), ExpandError> {
+        let clamp = |coordinate: usize| i32::try_from(coordinate).unwrap_or(i32::MAX);
+        self.expand_to(clamp(x), clamp(y), DEFAULT_MAX_DIMENSION)
+            .map(|_| ())
+    }
+
+    // Flood fills from `seed` regardless of tile type, stopping only at wall and door objects
+    pub fn fill_enclosed(&mut self, seed: (usize, usize), new_type: TileType) {
+        let is_boundary = |tile: &Tile| {
+            tile.objects()
+                .any(|object| matches!(object.object_type, ObjectType::Wall | ObjectType::Door))
+        };
+
+        let (x, y) = seed;
+        match self.get(x, y) {
+            Some(tile) if !is_boundary(tile) => {}
+            _ => return,
+        }
+
+        let mut visited: Vec<Vec<bool>> = self
+            .tiles
+            .iter()
+            .map(|row| vec![false; row.len()])
+            .collect();
+        let mut queue = VecDeque::from([seed]);
+        visited[y][x] = true;
+
+        while let Some((x, y)) = queue.pop_front() {
+            self.tiles[y][x].tile_type = new_type;
+
+            for (_, (nx, ny), neighbor) in self.neighbors(x, y) {
+                if !visited[ny][nx] && !is_boundary(neighbor) {
+                    visited[ny][nx] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+    }
+
+    // Puts a wall object on every blocked tile bordering a walkable one, existing objects like doors are kept
+    pub fn add_border_walls(&mut self) {
+        for ((x, y), direction) in self.walkable_perimeter() {
+            let Some(((x, y), _)) = self.neighbor(x, y, direction) else {
+                continue;
+            };
+
+            let tile = &mut self.tiles[y][x];
+            if tile.tile_type == TileType::Blocked && tile.object.is_none() {
+                tile.object = Some(Object::new(ObjectType::Wall, Quat::IDENTITY));
+            }
+        }
+    }
+
+    // Every tile as a block_size square in its theme color, row 0 at the top of the image
+    #[cfg(feature = "image")]
+    pub fn as_image_buffer(&self, block_size: u32, theme: &Theme) -> RgbaImage {
+        let (width, height) = self.dimensions();
+        let mut image = RgbaImage::new(width as u32 * block_size, height as u32 * block_size);
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let color = match &tile.object {
+                    Some(object) => theme.object_color(object.object_type),
+                    None => theme.tile_color(tile.tile_type),
+                };
+                let pixel = Rgba(color.as_rgba_u8());
+
+                for offset_y in 0..block_size {
+                    for offset_x in 0..block_size {
+                        image.put_pixel(
+                            x as u32 * block_size + offset_x,
+                            y as u32 * block_size + offset_y,
+                            pixel,
+                        );
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    // One line per row: the tile type's symbol, or `D` for a door and `+` for a wall object on a walkable tile.
+    // Objects on any other tile type, rotations, decorations, floor objects, connections and the spawn aren't
+    // written, so only maps without those come back unchanged from from_ascii
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::new();
+
+        for row in &self.tiles {
+            for tile in row {
+                ascii.push(match (&tile.object, tile.tile_type) {
+                    (Some(object), TileType::Walkable)
+                        if object.object_type == ObjectType::Door =>
+                    {
+                        'D'
+                    }
+                    (Some(object), TileType::Walkable)
+                        if object.object_type == ObjectType::Wall =>
+                    {
+                        '+'
+                    }
+                    (_, tile_type) => tile_type.symbol(),
+                });
+            }
+            ascii.push('\n');
+        }
+
+        ascii
+    }
+
+    // Reads the format written by to_ascii, every line has to be as wide as the first
+    pub fn from_ascii(ascii: &str) -> Result<Map, ParseError> {
+        let mut tiles: Vec<Vec<Tile>> = Vec::new();
+
+        for (y, line) in ascii.lines().enumerate() {
+            let row = line
+                .chars()
+                .enumerate()
+                .map(|(x, character)| match character {
+                    'D' => Ok(Tile::walkable().with_object(ObjectType::Door, Quat::IDENTITY)),
+                    '+' => Ok(Tile::walkable().with_object(ObjectType::Wall, Quat::IDENTITY)),
+                    _ => TileType::from_symbol(character)
+                        .map(|tile_type| Tile {
+                            tile_type,
+                            ..Default::default()
+                        })
+                        .ok_or(ParseError::UnknownCharacter {
+                            line: y + 1,
+                            column: x + 1,
+                            character,
+                        }),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if let Some(first) = tiles.first() {
+                if row.len() != first.len() {
+                    return Err(ParseError::Ragged {
+                        line: y + 1,
+                        expected: first.len(),
+                        found: row.len(),
+                    });
+                }
+            }
+
+            tiles.push(row);
+        }
+
+        Ok(Map { tiles, spawn: None })
+    }
+
+    // Each row on its own line as comma separated `count:symbol` runs of tile types, e.g. `3:#,2:.`.
+    // Only tile types are kept, objects and connections are dropped
+    pub fn to_rle(&self) -> String {
+        let mut rle = String::new();
+
+        for row in &self.tiles {
+            let mut runs: Vec<(usize, TileType)> = Vec::new();
+            for tile in row {
+                match runs.last_mut() {
+                    Some((count, tile_type)) if *tile_type == tile.tile_type => *count += 1,
+                    _ => runs.push((1, tile.tile_type)),
+                }
+            }
+
+            let runs: Vec<String> = runs
+                .into_iter()
+                .map(|(count, tile_type)| format!("{}:{}", count, tile_type.symbol()))
+                .collect();
+            rle.push_str(&runs.join(","));
+            rle.push('\n');
+        }
+
+        rle
+    }
+
+    // Reads the format written by to_rle, every row has to be as wide as the first and at most DEFAULT_MAX_DIMENSION
+    pub fn from_rle(rle: &str) -> Result<Map, ParseError> {
+        let mut tiles: Vec<Vec<Tile>> = Vec::new();
+
+        for (y, line) in rle.lines().enumerate() {
+            let mut row = Vec::new();
+
+            for run in line.split(',').filter(|run| !run.is_empty()) {
+                let invalid = || ParseError::InvalidRun {
+                    line: y + 1,
+                    run: run.to_string(),
+                };
+                let (count, symbol) = run.split_once(':').ok_or_else(invalid)?;
+                let count: usize = count.parse().map_err(|_| invalid())?;
+                let mut symbol = symbol.chars();
+                let tile_type = match (symbol.next(), symbol.next()) {
+                    (Some(symbol), None) => TileType::from_symbol(symbol).ok_or_else(invalid)?,
+                    _ => return Err(invalid()),
+                };
+
+                // a typo in a count shouldn't be able to allocate gigabytes
+                if row.len() + count > DEFAULT_MAX_DIMENSION {
+                    return Err(invalid());
+                }
+                row.resize(
+                    row.len() + count,
+                    Tile {
+                        tile_type,
+                        ..Default::default()
+                    },
+                );
+            }
+
+            if let Some(first) = tiles.first() {
+                if row.len() != first.len() {
+                    return Err(ParseError::Ragged {
+                        line: y + 1,
+                        expected: first.len(),
+                        found: row.len(),
+                    });
+                }
+            }
+
+            tiles.push(row);
+        }
+
+        Ok(Map { tiles, spawn: None })
+    }
+
+    // Wavefront OBJ of the geometry the game builds: a floor quad per open tile and a box per visible wall,
+    // at one unit per tile with rows along z and columns along x
+    pub fn to_obj(&self) -> String {
+        let mut obj = String::new();
+        let mut vertices = 0;
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let center = Vec3::new(x as f32, 0.0, y as f32);
+
+                if tile.tile_type != TileType::Blocked {
+                    push_obj_quad(&mut obj, &mut vertices, center, Vec3::Y);
+                }
+
+                if tile.is_wall()
+                    && self
+                        .neighbors(x, y)
+                        .any(|(_, _, neighbor)| !neighbor.is_wall())
+                {
+                    let center = center + Vec3::Y * 0.5;
+                    for normal in [
+                        Vec3::X,
+                        Vec3::NEG_X,
+                        Vec3::Y,
+                        Vec3::NEG_Y,
+                        Vec3::Z,
+                        Vec3::NEG_Z,
+                    ] {
+                        push_obj_quad(&mut obj, &mut vertices, center + normal * 0.5, normal);
+                    }
+                }
+            }
+        }
+
+        obj
+    }
+
+    // Steps needed to reach every walkable tile reachable from `from`, empty if `from` itself can't be stood on
+    pub fn distance_map(&self, from: (usize, usize)) -> HashMap<(usize, usize), u32> {
+        let mut distances = HashMap::new();
+        if !self.is_walkable(from.0, from.1) {
+            return distances;
+        }
+
+        distances.insert(from, 0);
+        let mut queue = VecDeque::from([from]);
+
+        while let Some((x, y)) = queue.pop_front() {
+            let distance = distances[&(x, y)];
+
+            for next in self.walkable_neighbors(x, y) {
+                if let Entry::Vacant(entry) = distances.entry(next) {
+                    entry.insert(distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        distances
+    }
+
+    // A shortest 4-connected path from `start` to `goal` including both ends, using A* with the manhattan distance.
+    // Blocked tiles and walls can't be crossed, None if either end can't be stood on or they aren't connected
+    pub fn find_path(
+        &self,
+        start: (usize, usize),
+        goal: (usize, usize),
+    ) -> Option<Vec<(usize, usize)>> {
+        if !self.is_walkable(start.0, start.1) || !self.is_walkable(goal.0, goal.1) {
+            return None;
+        }
+
+        let estimate = |(x, y): (usize, usize)| (x.abs_diff(goal.0) + y.abs_diff(goal.1)) as u32;
+        let mut steps = HashMap::from([(start, 0)]);
+        let mut came_from = HashMap::new();
+        let mut open = BinaryHeap::from([Reverse((estimate(start), start))]);
+
+        while let Some(Reverse((cost, current))) = open.pop() {
+            // a shorter way to this tile was queued after this entry
+            if cost > steps[&current] + estimate(current) {
+                continue;
+            }
+
+            if current == goal {
+                let mut path = vec![goal];
+                while let Some(&previous) = came_from.get(path.last()?) {
+                    path.push(previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let next_steps = steps[&current] + 1;
+            for next in self.walkable_neighbors(current.0, current.1) {
+                if steps.get(&next).is_some_and(|&known| known <= next_steps) {
+                    continue;
+                }
+
+                steps.insert(next, next_steps);
+                came_from.insert(next, current);
+                open.push(Reverse((next_steps + estimate(next), next)));
+            }
+        }
+
+        None
+    }
+
+    // The walkable tiles at most `steps` steps away from `from`, including `from`
+    pub fn tiles_within(&self, from: (usize, usize), steps: u32) -> HashSet<(usize, usize)> {
+        self.distance_map(from)
+            .into_iter()
+            .filter(|&(_, distance)| distance <= steps)
+            .map(|(tile, _)| tile)
+            .collect()
+    }
+
+    // True when every walkable tile can reach every other one, including when there are none
+    pub fn is_connected(&self) -> bool {
+        self.walkable_regions().len() <= 1
+    }
+
+    // Groups the walkable tiles into 4-connected regions of (x, y) coordinates, walls split regions and doors don't.
+    // Each region is in row-major order and regions are ordered by their first tile, so the output is stable
+    pub fn walkable_regions(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut visited: Vec<Vec<bool>> = self
+            .tiles
+            .iter()
+            .map(|row| vec![false; row.len()])
+            .collect();
+        let mut regions = Vec::new();
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for x in 0..row.len() {
+                if visited[y][x] || !self.is_walkable(x, y) {
+                    continue;
+                }
+
+                let mut region = Vec::new();
+                let mut queue = VecDeque::from([(x, y)]);
+                visited[y][x] = true;
+
+                while let Some((x, y)) = queue.pop_front() {
+                    region.push((x, y));
+
+                    for (nx, ny) in self.walkable_neighbors(x, y) {
+                        if !visited[ny][nx] {
+                            visited[ny][nx] = true;
+                            queue.push_back((nx, ny));
+                        }
+                    }
+                }
+
+                region.sort_unstable_by_key(|&(x, y)| (y, x));
+                regions.push(region);
+            }
+        }
+
+        regions
+    }
+
+    // Blocks off walkable regions smaller than `min_region_size`, returning how many tiles were removed.
+    // Tiles holding a connection are kept so portals don't disappear.
+    pub fn remove_isolated_walkable(&mut self, min_region_size: usize) -> usize {
+        self.walkable_regions()
+            .into_iter()
+            .filter(|region| region.len() < min_region_size)
+            .map(|region| self.block_region(&region))
+            .sum()
+    }
+
+    // Keeps only the walkable region containing `anchor` (plus any connection tiles) and trims around it.
+    // Does nothing if the anchor isn't on a walkable tile.
+    pub fn trim_to_region(&mut self, anchor: (usize, usize)) {
+        let regions = self.walkable_regions();
+        let Some(keep) = regions.iter().position(|region| region.contains(&anchor)) else {
+            return;
+        };
+
+        for (i, region) in regions.iter().enumerate() {
+            if i != keep {
+                self.block_region(region);
+            }
+        }
+
+        self.trim();
+    }
+
+    fn block_region(&mut self, region: &[(usize, usize)]) -> usize {
+        let mut blocked = 0;
+
+        for &(x, y) in region {
+            let tile = &mut self.tiles[y][x];
+            if tile.connection.is_none() {
+                tile.tile_type = TileType::Blocked;
+                blocked += 1;
+            }
+        }
+
+        blocked
+    }
+
+    // Grows the map until (x, y) is inside it, negative coordinates insert columns and rows at the front.
+    // Returns how many columns and rows were inserted at the front, which every existing (x, y) moved by
+    pub fn expand_to(
+        &mut self,
+        x: i32,
+        y: i32,
+        max_dimension: usize,
+    ) -> Result<(usize, usize), ExpandError> {
+        let (width, height) = self.dimensions();
+        // widen so huge coordinates can't overflow
+        let (x, y) = (i64::from(x), i64::from(y));
+        let (left, top) = ((-x).max(0), (-y).max(0));
+
+        let new_width = (width as i64).max(x + 1) + left;
+        let new_height = (height as i64).max(y + 1) + top;
+
+        if new_width > max_dimension as i64 || new_height > max_dimension as i64 {
+            return Err(ExpandError {
+                width: new_width,
+                height: new_height,
+                max_dimension,
+            });
+        }
+
+        let (left, top) = (left as usize, top as usize);
+        let (new_width, new_height) = (new_width as usize, new_height as usize);
+
+        for row in self.tiles.iter_mut() {
+            row.resize(new_width - left, Tile::default());
+            row.splice(0..0, vec![Tile::default(); left]);
+        }
+        let empty_row = vec![Tile::default(); new_width];
+        self.tiles.resize(new_height - top, empty_row.clone());
+        self.tiles.splice(0..0, vec![empty_row; top]);
+        if let Some((spawn_x, spawn_y)) = self.spawn.as_mut() {
+            *spawn_x += left;
+            *spawn_y += top;
+        }
+
+        Ok((left, top))
+    }
+
+    // Cuts the blocked margin around the other tiles, a map with nothing but blocked tiles is left as it is
+    pub fn trim(&mut self) {
+        let mut bounds: Option<((usize, usize), (usize, usize))> = None;
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if tile.tile_type == TileType::Blocked {
+                    continue;
+                }
+
+                bounds = Some(match bounds {
+                    Some(((min_x, min_y), (max_x, max_y))) => {
+                        ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+                    }
+                    None => ((x, y), (x, y)),
+                });
+            }
+        }
+
+        if let Some((top_left, bottom_right)) = bounds {
+            self.crop(top_left, bottom_right);
+        }
+    }
+
+    // Keeps only the rectangle between two opposite corners, inclusive and in either order.
+    // Corners past the edge are clamped to it. Local connections keep pointing at the same tile,
+    // those whose spawn was cut off are left as they were
+    pub fn crop(&mut self, top_left: (usize, usize), bottom_right: (usize, usize)) {
+        let (width, height) = self.dimensions();
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let clamp = |(x, y): (usize, usize)| (x.min(width - 1), y.min(height - 1));
+        let (top_left, bottom_right) = (clamp(top_left), clamp(bottom_right));
+        let (min_x, max_x) = (
+            top_left.0.min(bottom_right.0),
+            top_left.0.max(bottom_right.0),
+        );
+        let (min_y, max_y) = (
+            top_left.1.min(bottom_right.1),
+            top_left.1.max(bottom_right.1),
+        );
+
+        self.tiles = self.tiles[min_y..=max_y]
+            .iter()
+            .map(|row| {
+                (min_x..=max_x)
+                    .map(|x| {
+                        let mut tile = row.get(x).cloned().unwrap_or_default();
+                        if let Some(connection) = tile.connection.as_mut().filter(|c| c.is_local())
+                        {
+                            let (spawn_x, spawn_y) = connection.spawn;
+                            if (min_x..=max_x).contains(&spawn_x)
+                                && (min_y..=max_y).contains(&spawn_y)
+                            {
+                                connection.spawn = (spawn_x - min_x, spawn_y - min_y);
+                            }
+                        }
+                        tile
+                    })
+                    .collect()
+            })
+            .collect();
+        self.spawn = self
+            .spawn
+            .filter(|&(x, y)| (min_x..=max_x).contains(&x) && (min_y..=max_y).contains(&y))
+            .map(|(x, y)| (x - min_x, y - min_y));
+    }
+
+    // Turns the map a quarter clockwise with north up, a width x height map becomes height x width.
+    // Objects turn with it and local connections keep pointing at the same tile
+    pub fn rotate_90(&mut self) {
+        let (width, height) = self.dimensions();
+        // clockwise seen from above, with rows along +z in the game
+        let quarter = Quat::from_rotation_y(-FRAC_PI_2);
+        let mut rotated = vec![vec![Tile::default(); height]; width];
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let mut tile = tile.clone();
+                for object in tile.objects_mut() {
+                    object.rotation = snap_rotation(quarter * object.rotation);
+                }
+                if let Some(connection) = tile.connection.as_mut().filter(|c| c.is_local()) {
+                    let (spawn_x, spawn_y) = connection.spawn;
+                    if spawn_y < height {
+                        connection.spawn = (height - 1 - spawn_y, spawn_x);
+                    }
+                }
+                rotated[x][height - 1 - y] = tile;
+            }
+        }
+
+        self.tiles = rotated;
+        self.spawn = self
+            .spawn
+            .filter(|&(_, y)| y < height)
+            .map(|(x, y)| (height - 1 - y, x));
+    }
+
+    // Mirrors the map left to right, objects are mirrored too so a door facing east faces west.
+    // Connection spawns are not remapped, the map's own spawn is
+    pub fn flip_horizontal(&mut self) {
+        let (width, _) = self.dimensions();
+
+        for row in self.tiles.iter_mut() {
+            row.resize(width, Tile::default());
+            row.reverse();
+        }
+        self.spawn = self
+            .spawn
+            .filter(|&(x, _)| x < width)
+            .map(|(x, y)| (width - 1 - x, y));
+
+        // mirroring across x negates the y and z parts of the rotation
+        self.mirror_objects(|rotation| {
+            Quat::from_xyzw(rotation.x, -rotation.y, -rotation.z, rotation.w)
+        });
+    }
+
+    // Mirrors the map top to bottom, objects are mirrored too so a door facing north faces south.
+    // Connection spawns are not remapped, the map's own spawn is
+    pub fn flip_vertical(&mut self) {
+        let (_, height) = self.dimensions();
+
+        self.tiles.reverse();
+        self.spawn = self
+            .spawn
+            .filter(|&(_, y)| y < height)
+            .map(|(x, y)| (x, height - 1 - y));
+
+        // mirroring across z is mirroring across x followed by a half turn, rows run along z in the game
+        let half_turn = Quat::from_rotation_y(PI);
+        self.mirror_objects(|rotation| {
+            half_turn * Quat::from_xyzw(rotation.x, -rotation.y, -rotation.z, rotation.w)
+        });
+    }
+
+    fn mirror_objects(&mut self, mirror: impl Fn(Quat) -> Quat) {
+        for object in self.tiles.iter_mut().flatten().flat_map(Tile::objects_mut) {
+            object.rotation = snap_rotation(mirror(object.rotation));
+        }
+    }
+
+    // Moves the content by (dx, dy) within a grid grown by |dx| columns and |dy| rows so nothing falls off,
+    // vacated cells are filled with Tile::default() and local connections keep pointing at the same tile
+    pub fn shift(&mut self, dx: i32, dy: i32) {
+        let grow = |amount: i32| amount.max(0) as usize;
+        self.pad_each(grow(dy), grow(-dy), grow(dx), grow(-dx));
+    }
+
+    pub fn pad(&mut self, padding: usize) {
+        self.pad_each(padding, padding, padding, padding);
+    }
+
+    // Adds Tile::default() rows and columns on each side, local connections keep pointing at the same tile
+    pub fn pad_each(&mut self, top: usize, bottom: usize, left: usize, right: usize) {
+        let (width, height) = self.dimensions();
+
+        let mut new_tiles =
+            vec![vec![Tile::default(); left + width + right]; top + height + bottom];
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let mut tile = tile.clone();
+                if let Some(connection) = tile.connection.as_mut().filter(|c| c.is_local()) {
+                    connection.spawn = (connection.spawn.0 + left, connection.spawn.1 + top);
+                }
+                new_tiles[y + top][x + left] = tile;
+            }
+        }
+
+        self.tiles = new_tiles;
+        if let Some((spawn_x, spawn_y)) = self.spawn.as_mut() {
+            *spawn_x += left;
+            *spawn_y += top;
+        }
+    }
+}
+
+// FNV-1a, unlike DefaultHasher its output is fixed
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+// Writes a unit quad centered on `center` facing along `normal`, wound counter-clockwise
+fn push_obj_quad(obj: &mut String, vertices: &mut usize, center: Vec3, normal: Vec3) {
+    let up = if normal.y != 0.0 {
+        Vec3::NEG_Z
+    } else {
+        Vec3::Y
+    };
+    let u = up.cross(normal) * 0.5;
+    let v = up * 0.5;
+
+    for corner in [
+        center - u - v,
+        center + u - v,
+        center + u + v,
+        center - u + v,
+    ] {
+        obj.push_str(&format!("v {} {} {}\n", corner.x, corner.y, corner.z));
+    }
+
+    obj.push_str(&format!(
+        "f {} {} {} {}\n",
+        *vertices + 1,
+        *vertices + 2,
+        *vertices + 3,
+        *vertices + 4
+    ));
+    *vertices += 4;
+}
+
+// Points the connection on a_pos in `a` at b_pos in `b` and the one on b_pos back at a_pos, so neither
+// side is a one way portal. Does nothing unless both positions are inside their maps
+pub fn link_connection(
+    a: &mut Map,
+    a_pos: (usize, usize),
+    a_name: &str,
+    b: &mut Map,
+    b_pos: (usize, usize),
+    b_name: &str,
+) {
+    if a.get(a_pos.0, a_pos.1).is_none() || b.get(b_pos.0, b_pos.1).is_none() {
+        return;
+    }
+
+    for (map, (x, y), target, spawn) in [(a, a_pos, b_name, b_pos), (b, b_pos, a_name, a_pos)] {
+        map.tiles[y][x].connection = Some(Connection {
+            map: target.to_string(),
+            spawn,
+            floor: None,
+        });
+    }
+}
+
+// link_connection that also makes both tiles walkable so each spawn can be stood on
+pub fn link_maps(
+    a: &mut Map,
+    a_pos: (usize, usize),
+    a_name: &str,
+    b: &mut Map,
+    b_pos: (usize, usize),
+    b_name: &str,
+) {
+    if a.get(a_pos.0, a_pos.1).is_none() || b.get(b_pos.0, b_pos.1).is_none() {
+        return;
+    }
+
+    a.tiles[a_pos.1][a_pos.0].tile_type = TileType::Walkable;
+    b.tiles[b_pos.1][b_pos.0].tile_type = TileType::Walkable;
+    link_connection(a, a_pos, a_name, b, b_pos, b_name);
+}
+
+// Every (x, y) on the Bresenham line from `from` to `to`, both ends included. Each step moves to one of
+// the 8 neighbors so the line never leaves a gap
+pub fn line_points(from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = (i64::from(from.0), i64::from(from.1));
+    let (end_x, end_y) = (i64::from(to.0), i64::from(to.1));
+    let (dx, dy) = ((end_x - x).abs(), -(end_y - y).abs());
+    let (step_x, step_y) = ((end_x - x).signum(), (end_y - y).signum());
+    let mut error = dx + dy;
+    let mut points = Vec::new();
+
+    loop {
+        points.push((x as i32, y as i32));
+        if (x, y) == (end_x, end_y) {
+            return points;
+        }
+
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+// Floors stacked on top of each other, joined by stair connections that name a floor index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct MultiFloorMap {
+    pub floors: Vec<Map>,
+}
+
+impl From<Map> for MultiFloorMap {
+    fn from(map: Map) -> Self {
+        Self { floors: vec![map] }
+    }
+}
+
+impl MultiFloorMap {
+    pub fn decode(bytes: &[u8]) -> Result<MultiFloorMap, MapError> {
+        let world: MultiFloorMap =
+            serde_json::from_slice(bytes).map_err(|error| MapError::Parse(error.to_string()))?;
+        if world.floors.is_empty() {
+            return Err(MapError::Validation("no floors".to_string()));
+        }
+        for (index, floor) in world.floors.iter().enumerate() {
+            if let Err(MapError::Validation(error)) = floor.validate() {
+                return Err(MapError::Validation(format!("floor {}: {}", index, error)));
+            }
+        }
+        Ok(world)
+    }
+
+    // A multi-floor file, or any single map file as a building with one floor
+    pub fn load_detect(bytes: &[u8]) -> Result<MultiFloorMap, MapError> {
+        match MultiFloorMap::decode(bytes) {
+            Err(MapError::Parse(_)) => Map::load_detect(bytes).map(MultiFloorMap::from),
+            result => result,
+        }
+    }
+
+    pub fn load_from_path(path: impl AsRef<Path>) -> Result<MultiFloorMap, MapError> {
+        let path = path.as_ref();
+        match MultiFloorMap::decode(&std::fs::read(path)?) {
+            Err(MapError::Parse(_)) => Map::load_from_path(path).map(MultiFloorMap::from),
+            result => result,
+        }
+    }
+
+    // The floor and (x, y) reached by taking the stairs at (x, y) on `floor`, if there are stairs
+    // there and they lead somewhere walkable
+    pub fn take_stairs(&self, floor: usize, x: usize, y: usize) -> Option<(usize, (usize, usize))> {
+        let connection = self
+            .floors
+            .get(floor)?
+            .tiles
+            .get(y)?
+            .get(x)?
+            .connection
+            .as_ref()?;
+        let target = connection.floor?;
+        let (spawn_x, spawn_y) = connection.spawn;
+
+        self.floors
+            .get(target)?
+            .is_walkable(spawn_x, spawn_y)
+            .then_some((target, connection.spawn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash_of(value: &impl Hash) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn signed_zero_rotations_hash_the_same() {
+        let positive = Object {
+            object_type: ObjectType::Door,
+            rotation: Quat::from_xyzw(0.0, 0.0, 0.0, 1.0),
+        };
+        let negative = Object {
+            rotation: Quat::from_xyzw(-0.0, -0.0, -0.0, 1.0),
+            ..positive
+        };
+
+        assert_eq!(positive, negative);
+        assert_eq!(hash_of(&positive), hash_of(&negative));
+
+        let map = |object| Map {
+            tiles: vec![vec![Tile {
+                object: Some(object),
+                ..Tile::walkable()
+            }]],
+            spawn: None,
+        };
+        assert_eq!(map(positive).content_hash(), map(negative).content_hash());
+    }
+
+    fn walls(map: &Map) -> Vec<(usize, usize)> {
+        let mut walls = Vec::new();
+        for (y, row) in map.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if tile
+                    .object
+                    .is_some_and(|object| object.object_type == ObjectType::Wall)
+                {
+                    walls.push((x, y));
+                }
+            }
+        }
+        walls
+    }
+
+    #[test]
+    fn border_walls_ring_a_room() {
+        let mut map = Map::new_blocked(5, 5);
+        for y in 1..4 {
+            for x in 1..4 {
+                map.tiles[y][x] = Tile::walkable();
+            }
+        }
+        map.tiles[0][2] = Tile::blocked().with_object(ObjectType::Door, Quat::IDENTITY);
+
+        map.add_border_walls();
+
+        // diagonal corners don't touch the room, the door is kept
+        let mut expected = Vec::new();
+        for y in 0..5 {
+            for x in 0..5 {
+                let ring = x == 0 || x == 4 || y == 0 || y == 4;
+                let corner = (x == 0 || x == 4) && (y == 0 || y == 4);
+                if ring && !corner && (x, y) != (2, 0) {
+                    expected.push((x, y));
+                }
+            }
+        }
+        assert_eq!(walls(&map), expected);
+        assert_eq!(
+            map.tiles[0][2].object.unwrap().object_type,
+            ObjectType::Door
+        );
+    }
+
+    #[test]
+    fn border_walls_follow_an_l_shape() {
+        // .##
+        // .##
+        // ...
+        let mut map = Map::new_blocked(3, 3);
+        for (x, y) in [(0, 0), (0, 1), (0, 2), (1, 2), (2, 2)] {
+            map.tiles[y][x] = Tile::walkable();
+        }
+
+        map.add_border_walls();
+
+        assert_eq!(walls(&map), vec![(1, 0), (1, 1), (2, 1)]);
+        assert!(map.tiles[0][2].object.is_none());
+    }
+
+    #[test]
+    fn border_walls_leave_water_and_hazards_alone() {
+        let mut map = Map::new_blocked(3, 1);
+        map.tiles[0][0].tile_type = TileType::Water;
+        map.tiles[0][1] = Tile::walkable();
+        map.tiles[0][2].tile_type = TileType::Hazard;
+
+        map.add_border_walls();
+
+        assert!(walls(&map).is_empty());
+    }
+
+    fn two_floors() -> MultiFloorMap {
+        let mut ground = Map::from_ascii("...\n").unwrap();
+        ground.tiles[0][2] = Tile::walkable().with_stairs(1, (0, 0));
+        let mut upstairs = Map::from_ascii(".#\n").unwrap();
+        upstairs.tiles[0][0] = Tile::walkable().with_stairs(0, (2, 0));
+
+        MultiFloorMap {
+            floors: vec![ground, upstairs],
+        }
+    }
+
+    #[test]
+    fn stairs_move_between_floors() {
+        let world = two_floors();
+
+        assert_eq!(world.take_stairs(0, 2, 0), Some((1, (0, 0))));
+        assert_eq!(world.take_stairs(1, 0, 0), Some((0, (2, 0))));
+        assert_eq!(world.take_stairs(0, 1, 0), None);
+        assert_eq!(world.take_stairs(2, 0, 0), None);
+    }
+
+    #[test]
+    fn stairs_onto_blocked_tiles_go_nowhere() {
+        let mut world = two_floors();
+        world.floors[0].tiles[0][0] = Tile::walkable().with_stairs(1, (1, 0));
+
+        assert_eq!(world.take_stairs(0, 0, 0), None);
+    }
+
+    #[test]
+    fn multi_floor_maps_round_trip() {
+        let world = two_floors();
+        let bytes = serde_json::to_vec(&world).unwrap();
+
+        assert_eq!(MultiFloorMap::decode(&bytes).unwrap(), world);
+        assert_eq!(MultiFloorMap::load_detect(&bytes).unwrap(), world);
+    }
+
+    #[test]
+    fn single_maps_load_as_one_floor() {
+        let map = two_floors().floors.remove(0);
+
+        for format in [MapFormat::Json, MapFormat::Binary] {
+            let world = MultiFloorMap::load_detect(&map.encode(format).unwrap()).unwrap();
+            assert_eq!(world.floors, vec![map.clone()]);
+        }
+    }
+
+    #[test]
+    fn multi_floor_maps_need_a_floor() {
+        assert!(matches!(
+            MultiFloorMap::decode(br#"{"floors":[]}"#),
+            Err(MapError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn ascii_round_trips() {
+        let ascii = "#####\n#.D~#\n#+.^#\n#####\n";
+        let map = Map::from_ascii(ascii).unwrap();
+
+        assert_eq!(
+            map.tiles[1][2].object.unwrap().object_type,
+            ObjectType::Door
+        );
+        assert_eq!(
+            map.tiles[2][1].object.unwrap().object_type,
+            ObjectType::Wall
+        );
+        assert_eq!(map.tiles[1][3].tile_type, TileType::Water);
+        assert_eq!(map.to_ascii(), ascii);
+        assert_eq!(Map::from_ascii(&map.to_ascii()).unwrap(), map);
+    }
+
+    #[test]
+    fn ascii_drops_objects_off_walkable_tiles() {
+        let mut map = Map::from_ascii("~^#\n").unwrap();
+        for tile in &mut map.tiles[0] {
+            tile.object = Some(Object::new(ObjectType::Door, Quat::IDENTITY));
+        }
+
+        assert_eq!(map.to_ascii(), "~^#\n");
+    }
+
+    #[test]
+    fn ascii_rejects_ragged_rows() {
+        assert_eq!(
+            Map::from_ascii("...\n..\n"),
+            Err(ParseError::Ragged {
+                line: 2,
+                expected: 3,
+                found: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn content_hash_follows_content() {
+        let mut map = Map::new_blocked(3, 3);
+        let copy = map.clone();
+        assert_eq!(map.content_hash(), copy.content_hash());
+        assert_eq!(map.content_hash(), Map::new_blocked(3, 3).content_hash());
+
+        map.tiles[1][1] = Tile::walkable();
+        assert_ne!(map.content_hash(), copy.content_hash());
+    }
+
+    #[test]
+    fn expand_to_refuses_oversized_maps() {
+        let mut map = Map::default();
+        assert_eq!(map.expand_to(2, 3, 10), Ok((0, 0)));
+        assert_eq!(map.dimensions(), (3, 4));
+
+        for (x, y) in [(i32::MAX, 0), (0, i32::MIN), (10, 0), (-8, 0)] {
+            assert!(map.expand_to(x, y, 10).is_err(), "({}, {})", x, y);
+        }
+        assert_eq!(map.dimensions(), (3, 4));
+
+        assert_eq!(
+            map.expand_to(0, 20, 10),
+            Err(ExpandError {
+                width: 3,
+                height: 21,
+                max_dimension: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn builder_fills_every_field() {
+        let rotation = Quat::from_rotation_y(FRAC_PI_2);
+        let tile = Tile::walkable()
+            .with_object(ObjectType::Door, rotation)
+            .with_floor_object(ObjectType::Wall)
+            .with_connection("cellar.map", (2, 3));
+
+        assert_eq!(tile.tile_type, TileType::Walkable);
+        assert_eq!(tile.object, Some(Object::new(ObjectType::Door, rotation)));
+        assert_eq!(
+            tile.floor_object,
+            Some(FloorObject {
+                object_type: ObjectType::Wall,
+            })
+        );
+        assert_eq!(
+            tile.connection,
+            Some(Connection {
+                map: "cellar.map".to_string(),
+                spawn: (2, 3),
+                floor: None,
+            })
+        );
+        assert!(tile.decorations.is_empty());
+
+        assert_eq!(Tile::blocked(), Tile::default());
+        assert_eq!(Tile::blocked().tile_type, TileType::Blocked);
+    }
+
+    #[test]
+    fn formats_are_sniffed_from_their_first_bytes() {
+        let map = Map::new_blocked(3, 2);
+
+        assert_eq!(
+            MapFormat::sniff(b"  \n{\"tiles\": []}"),
+            Some(MapFormat::Json)
+        );
+        assert_eq!(MapFormat::sniff(&map.to_bytes()), Some(MapFormat::Binary));
+        assert_eq!(MapFormat::sniff(b"tiles"), None);
+        assert_eq!(MapFormat::sniff(b""), None);
+
+        assert_eq!(Map::load_detect(&map.to_bytes()).unwrap(), map);
+        assert!(matches!(
+            Map::load_detect(b"tiles"),
+            Err(MapError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn small_regions_are_removed_but_portals_stay() {
+        let mut map = Map::from_ascii("#####\n#..##\n#..#.\n####.\n").unwrap();
+        map.tiles[3][4] = Tile::walkable().with_connection("cellar.map", (0, 0));
+        assert_eq!(map.walkable_regions().len(), 2);
+
+        assert_eq!(map.remove_isolated_walkable(3), 1);
+        assert_eq!(map.tiles[2][4].tile_type, TileType::Blocked);
+        assert_eq!(map.tiles[3][4].tile_type, TileType::Walkable);
+        assert_eq!(map.tiles[1][1].tile_type, TileType::Walkable);
+    }
+
+    #[test]
+    fn trimming_to_a_region_keeps_only_that_room() {
+        let mut map = Map::from_ascii("#######\n#..#..#\n#..#..#\n#######\n").unwrap();
+        map.trim_to_region((4, 1));
+        assert_eq!(map, Map::from_ascii("..\n..\n").unwrap());
+
+        let mut map = Map::from_ascii("#.#\n").unwrap();
+        map.trim_to_region((0, 0));
+        assert_eq!(map, Map::from_ascii("#.#\n").unwrap());
+    }
+
+    #[test]
+    fn perimeter_counts_edges_facing_walls_and_the_map_edge() {
+        let room = Map::from_ascii("#####\n#...#\n#...#\n#####\n").unwrap();
+        assert_eq!(room.walkable_perimeter().len(), 10);
+
+        let open = Map::from_ascii("..\n..\n").unwrap();
+        let perimeter = open.walkable_perimeter();
+        assert_eq!(perimeter.len(), 8);
+        assert!(perimeter.contains(&((0, 0), Direction::North)));
+        assert!(!perimeter.contains(&((0, 0), Direction::East)));
+    }
+
+    #[test]
+    fn obj_has_a_quad_per_floor_and_a_box_per_visible_wall() {
+        let count =
+            |obj: &str, prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+
+        let floor = Map::from_ascii(".\n").unwrap().to_obj();
+        assert_eq!(count(&floor, "v "), 4);
+        assert_eq!(count(&floor, "f "), 1);
+
+        let corridor = Map::from_ascii(".#\n").unwrap().to_obj();
+        assert_eq!(count(&corridor, "v "), 28);
+        assert_eq!(count(&corridor, "f "), 7);
+
+        // walls with no open neighbor are hidden
+        assert_eq!(count(&Map::from_ascii("##\n").unwrap().to_obj(), "f "), 0);
+    }
+
+    #[test]
+    fn enclosed_fill_stops_at_walls_and_leaks_through_gaps() {
+        let walkable = |map: &Map| {
+            map.tiles
+                .iter()
+                .flatten()
+                .filter(|tile| tile.tile_type == TileType::Walkable)
+                .count()
+        };
+
+        // only the blocked tiles inside the ring are filled
+        let mut map = Map::from_ascii("+++++#\n+###+#\n+###+#\n+++++#\n").unwrap();
+        map.fill_enclosed((1, 1), TileType::Water);
+        assert_eq!(map.tiles[1][1].tile_type, TileType::Water);
+        assert_eq!(map.tiles[2][3].tile_type, TileType::Water);
+        assert_eq!(map.tiles[0][5].tile_type, TileType::Blocked);
+        assert_eq!(walkable(&map), 14);
+
+        let mut map = Map::from_ascii("+++++#\n+###+#\n+###.#\n+++++#\n").unwrap();
+        map.fill_enclosed((1, 1), TileType::Water);
+        assert_eq!(map.tiles[0][5].tile_type, TileType::Water);
+
+        // seeding on a wall does nothing
+        let mut map = Map::from_ascii("+#\n").unwrap();
+        map.fill_enclosed((0, 0), TileType::Water);
+        assert_eq!(map, Map::from_ascii("+#\n").unwrap());
+    }
+
+    #[test]
+    fn load_errors_say_what_went_wrong() {
+        let directory =
+            std::env::temp_dir().join(format!("map-load-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let missing = Map::load_from_path(directory.join("missing.map"));
+        assert!(matches!(missing, Err(MapError::Io(_))));
+
+        let malformed = directory.join("malformed.map");
+        std::fs::write(&malformed, "{\"tiles\": [[").unwrap();
+        assert!(matches!(
+            Map::load_from_path(&malformed),
+            Err(MapError::Parse(_))
+        ));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn walls_block_walkable_tiles() {
+        let map = Map::from_ascii(".+\n#.\n").unwrap();
+
+        assert!(map.is_walkable(0, 0));
+        assert!(map.is_walkable(1, 1));
+        assert!(!map.is_walkable(1, 0));
+        assert!(!map.is_walkable(0, 1));
+        assert!(!map.is_walkable(2, 0));
+        assert!(!map.is_walkable(0, 2));
+    }
+
+    #[test]
+    fn rotations_load_normalized() {
+        let object: Object =
+            serde_json::from_str(r#"{"object_type": "Wall", "rotation": [0.0, 2.0, 0.0, 2.0]}"#)
+                .unwrap();
+        assert!(object.rotation.is_normalized());
+        assert!(object
+            .rotation
+            .abs_diff_eq(Quat::from_rotation_y(FRAC_PI_2), 1e-6));
+
+        assert_eq!(
+            normalize_rotation(Quat::from_xyzw(0.0, 0.0, 0.0, 0.0)),
+            Quat::IDENTITY
+        );
+        assert_eq!(
+            normalize_rotation(Quat::from_xyzw(f32::NAN, 0.0, 0.0, 1.0)),
+            Quat::IDENTITY
+        );
+    }
+
+    #[test]
+    fn new_blocked_maps_and_emptiness() {
+        let map = Map::new_blocked(3, 2);
+        assert_eq!(map.dimensions(), (3, 2));
+        assert!(map
+            .tiles
+            .iter()
+            .flatten()
+            .all(|tile| *tile == Tile::blocked()));
+        assert!(!map.is_empty());
+
+        assert!(Map::default().is_empty());
+        assert!(Map::new_blocked(0, 4).is_empty());
+        assert!(Map::new_blocked(4, 0).is_empty());
+    }
+
+    #[cfg(feature = "gen")]
+    #[test]
+    fn generated_maps_are_deterministic_and_connected() {
+        for seed in 0..10 {
+            for (width, height) in [(3, 3), (4, 7), (21, 15), (2, 9)] {
+                for generate in [Map::generate_maze, Map::generate_rooms] {
+                    let map = generate(width, height, seed);
+                    assert_eq!(map, generate(width, height, seed));
+                    assert_eq!(map.dimensions(), (width, height));
+                    assert!(map.is_connected());
+
+                    let walkable = map.tiles.iter().flatten().filter(|tile| tile.is_passable());
+                    assert_eq!(walkable.count() > 0, width >= 3 && height >= 3);
+                }
+            }
+        }
+
+        assert_ne!(Map::generate_maze(21, 21, 1), Map::generate_maze(21, 21, 2));
+    }
+
+    #[test]
+    fn every_variant_has_its_own_color() {
+        fn all_distinct(colors: Vec<Color>) -> bool {
+            colors
+                .iter()
+                .enumerate()
+                .all(|(i, color)| !colors[i + 1..].contains(color))
+        }
+
+        assert!(all_distinct(
+            TileType::ALL.iter().map(TileType::editor_color).collect()
+        ));
+        assert!(all_distinct(
+            TileType::ALL.iter().map(TileType::game_color).collect()
+        ));
+        assert!(all_distinct(
+            ObjectType::ALL.iter().map(ObjectType::game_color).collect()
+        ));
+    }
+
+    #[test]
+    fn clearing_a_tile_drops_everything_on_it() {
+        let mut tile = Tile::walkable()
+            .with_object(ObjectType::Door, Quat::IDENTITY)
+            .with_floor_object(ObjectType::Wall)
+            .with_connection("cellar.map", (1, 2));
+        tile.clear();
+        assert_eq!(tile, Tile::blocked());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn image_buffer_paints_each_tile_a_block() {
+        let mut map = Map::new_blocked(3, 2);
+        map.tiles[1][2] = Tile::walkable();
+        let theme = Theme::default();
+
+        let image = map.as_image_buffer(4, &theme);
+        assert_eq!(image.dimensions(), (12, 8));
+        assert_eq!(
+            image.get_pixel(2 * 4 + 2, 4 + 2).0,
+            theme.tile_color(TileType::Walkable).as_rgba_u8()
+        );
+        assert_eq!(
+            image.get_pixel(1, 1).0,
+            theme.tile_color(TileType::Blocked).as_rgba_u8()
+        );
+    }
+
+    #[test]
+    fn tiles_are_gathered_and_set_by_type() {
+        let mut map = Map::from_ascii("#.#\n##.\n").unwrap();
+        assert_eq!(map.tiles_of_type(TileType::Walkable), vec![(1, 0), (2, 1)]);
+
+        let blocked = map.tiles_of_type(TileType::Blocked);
+        assert_eq!(blocked, vec![(0, 0), (2, 0), (0, 1), (1, 1)]);
+
+        map.set_type_at(&blocked, TileType::Water);
+        // coordinates outside the map are skipped
+        map.set_type_at(&[(9, 9)], TileType::Blocked);
+        assert_eq!(map, Map::from_ascii("~.~\n~~.\n").unwrap());
+    }
+
+    #[test]
+    fn linked_maps_point_at_each_other() {
+        let mut a = Map::new_blocked(3, 3);
+        let mut b = Map::new_blocked(4, 2);
+        link_maps(&mut a, (2, 1), "a.map", &mut b, (3, 0), "b.map");
+
+        assert_eq!(
+            a.tiles[1][2],
+            Tile::walkable().with_connection("b.map", (3, 0))
+        );
+        assert_eq!(
+            b.tiles[0][3],
+            Tile::walkable().with_connection("a.map", (2, 1))
+        );
+
+        // neither side changes if either tile is outside its map
+        let (before_a, before_b) = (a.clone(), b.clone());
+        link_maps(&mut a, (0, 0), "a.map", &mut b, (9, 9), "b.map");
+        assert_eq!((a, b), (before_a, before_b));
+    }
+
+    #[test]
+    fn distances_go_around_walls() {
+        let map = Map::from_ascii("...\n##.\n...\n").unwrap();
+
+        let distances = map.distance_map((0, 0));
+        assert_eq!(distances.len(), 7);
+        assert_eq!(distances[&(0, 0)], 0);
+        assert_eq!(distances[&(2, 0)], 2);
+        assert_eq!(distances[&(2, 2)], 4);
+        assert_eq!(distances[&(0, 2)], 6);
+        assert!(map.distance_map((0, 1)).is_empty());
+
+        assert_eq!(
+            map.tiles_within((0, 0), 2),
+            HashSet::from([(0, 0), (1, 0), (2, 0)])
+        );
+    }
+
+    #[test]
+    fn corrupt_saves_recover_from_the_backup() {
+        let directory = std::env::temp_dir().join(format!("map-backup-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("level.map");
+        assert_eq!(backup_path(&path), directory.join("level.map.bak"));
+
+        let first = Map::from_ascii("..\n").unwrap();
+        let second = Map::from_ascii(".#\n").unwrap();
+        first.save_to_path(&path).unwrap();
+        second.save_to_path(&path).unwrap();
+        assert_eq!(Map::load_from_path(backup_path(&path)).unwrap(), first);
+        assert_eq!(Map::load_or_backup(&path).unwrap(), (second, false));
+
+        std::fs::write(&path, "{\"tiles\": [[").unwrap();
+        assert_eq!(Map::load_or_backup(&path).unwrap(), (first, true));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(matches!(Map::load_or_backup(&path), Err(MapError::Io(_))));
+    }
+
+    #[test]
+    fn failed_saves_say_so_and_clean_up() {
+        let directory = std::env::temp_dir().join(format!("map-save-{}", std::process::id()));
+        // a directory where the map should go can't be backed up or replaced
+        let path = directory.join("level.map");
+        std::fs::create_dir_all(&path).unwrap();
+
+        let error = Map::from_ascii("..\n")
+            .unwrap()
+            .save_to_path(&path)
+            .unwrap_err();
+        assert!(matches!(error, MapError::Write(_)));
+        assert!(
+            error.to_string().starts_with("Failed to write map: "),
+            "{}",
+            error
+        );
+        assert!(!directory.join("level.map.tmp").exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn shifting_moves_content_and_local_spawns() {
+        let mut map = Map::new_blocked(2, 2);
+        map.tiles[0][0] = Tile::walkable().with_connection("", (1, 1));
+        map.tiles[1][1] = Tile::walkable().with_connection("other.map", (1, 1));
+
+        let mut shifted = map.clone();
+        shifted.shift(2, 1);
+        assert_eq!(shifted.dimensions(), (4, 3));
+        assert_eq!(
+            shifted.tiles[1][2],
+            Tile::walkable().with_connection("", (3, 2))
+        );
+        assert_eq!(shifted.tiles[2][3], map.tiles[1][1]);
+        assert_eq!(shifted.tiles[0][0], Tile::default());
+
+        let mut shifted = map.clone();
+        shifted.shift(-1, -2);
+        assert_eq!(shifted.dimensions(), (3, 4));
+        assert_eq!(shifted.tiles[0][0], map.tiles[0][0]);
+        assert_eq!(shifted.tiles[1][1], map.tiles[1][1]);
+        assert_eq!(shifted.tiles[3][2], Tile::default());
+    }
+
+    #[test]
+    fn regions_and_connection_errors_come_in_row_major_order() {
+        let mut map = Map::from_ascii("###.#\n#...#\n#.###\n.###.\n").unwrap();
+        assert_eq!(
+            map.walkable_regions(),
+            vec![
+                vec![(3, 0), (1, 1), (2, 1), (3, 1), (1, 2)],
+                vec![(0, 3)],
+                vec![(4, 3)],
+            ]
+        );
+
+        map.tiles[3][4] = Tile::walkable().with_connection("b.map", (0, 0));
+        map.tiles[0][3] = Tile::walkable().with_connection("a.map", (0, 0));
+        let errors = map.validate_connections(|_| None);
+        assert_eq!(
+            errors.iter().map(ConnectionError::at).collect::<Vec<_>>(),
+            vec![(3, 0), (4, 3)]
+        );
+    }
+
+    fn arb_object() -> impl Strategy<Value = Object> {
+        (prop::sample::select(ObjectType::ALL.to_vec()), -PI..PI)
+            .prop_map(|(object_type, angle)| Object::new(object_type, Quat::from_rotation_y(angle)))
+    }
+
+    fn arb_tile() -> impl Strategy<Value = Tile> {
+        let connection = (
+            "[a-z]{0,8}",
+            (0..32usize, 0..32usize),
+            prop::option::of(0..4usize),
+        )
+            .prop_map(|(map, spawn, floor)| Connection { map, spawn, floor });
+
+        (
+            prop::sample::select(TileType::ALL.to_vec()),
+            prop::option::of(arb_object()),
+            prop::option::of(prop::sample::select(ObjectType::ALL.to_vec())),
+            prop::option::of(connection),
+            prop::collection::vec(arb_object(), 0..3),
+        )
+            .prop_map(
+                |(tile_type, object, floor_object, connection, decorations)| Tile {
+                    tile_type,
+                    object,
+                    floor_object: floor_object.map(|object_type| FloorObject { object_type }),
+                    connection,
+                    decorations,
+                },
+            )
+    }
+
+    // sizes are kept small so each case stays fast
+    fn arb_map() -> impl Strategy<Value = Map> {
+        (0..12usize, 0..12usize)
+            .prop_flat_map(|(width, height)| {
+                (
+                    prop::collection::vec(prop::collection::vec(arb_tile(), width), height),
+                    prop::option::of((0..12usize, 0..12usize)),
+                )
+            })
+            .prop_map(|(tiles, spawn)| Map { tiles, spawn })
+    }
+
+    proptest! {
+        #[test]
+        fn maps_round_trip_through_json(map in arb_map()) {
+            let json = map.encode(MapFormat::Json).unwrap();
+            prop_assert_eq!(Map::decode(&json, MapFormat::Json).unwrap(), map);
+        }
+
+        #[test]
+        fn maps_round_trip_through_binary(map in arb_map()) {
+            prop_assert_eq!(Map::from_bytes(&map.to_bytes()).unwrap(), map);
+        }
+    }
+
+    #[test]
+    fn binary_and_json_compared_on_a_big_map() {
+        let mut map = Map::new_blocked(256, 256);
+        for (y, row) in map.tiles.iter_mut().enumerate() {
+            for (x, tile) in row.iter_mut().enumerate() {
+                if x % 4 != 0 && y % 4 != 0 {
+                    *tile = Tile::walkable();
+                } else if (x + y) % 8 == 0 {
+                    *tile = Tile::walkable().with_object(ObjectType::Door, Quat::IDENTITY);
+                }
+            }
+        }
+
+        // Measured on this map, encoding and decoding once in a release build:
+        //   json    6230552 bytes, 26ms
+        //   binary  1026061 bytes, 8ms
+        // Timings vary too much between machines to assert on, so only the sizes are checked
+        let size = |format: MapFormat| {
+            let bytes = map.encode(format).unwrap();
+            assert_eq!(Map::decode(&bytes, format).unwrap(), map);
+            bytes.len()
+        };
+        let (json_size, binary_size) = (size(MapFormat::Json), size(MapFormat::Binary));
+
+        assert!(binary_size * 2 < json_size);
+    }
+
+    #[test]
+    fn get_is_bounds_checked() {
+        assert!(Map::default().get(0, 0).is_none());
+
+        // ragged rows are checked per row
+        let mut map = Map {
+            tiles: vec![vec![Tile::walkable(); 3], vec![Tile::blocked()]],
+            spawn: None,
+        };
+        assert_eq!(map.get(2, 0), Some(&Tile::walkable()));
+        assert!(map.get(3, 0).is_none());
+        assert!(map.get(1, 1).is_none());
+        assert!(map.get(0, 2).is_none());
+
+        map.get_mut(0, 1).unwrap().tile_type = TileType::Walkable;
+        assert!(map.is_walkable(0, 1));
+        assert!(map.get_mut(5, 5).is_none());
+    }
+
+    #[test]
+    fn dimensions_use_the_widest_row() {
+        assert_eq!(Map::default().dimensions(), (0, 0));
+        assert_eq!(Map::new_blocked(4, 3).dimensions(), (4, 3));
+
+        let ragged = Map {
+            tiles: vec![vec![Tile::walkable(); 2], vec![Tile::walkable(); 5], vec![]],
+            spawn: None,
+        };
+        assert_eq!(ragged.dimensions(), (5, 3));
+    }
+
+    #[test]
+    fn negative_coordinates_grow_the_map_at_the_front() {
+        let mut map = Map::default();
+        assert_eq!(map.expand_to(-3, -2, 64), Ok((3, 2)));
+        assert_eq!(map.dimensions(), (3, 2));
+
+        let mut map = Map::new_blocked(2, 2);
+        map.tiles[0][0] = Tile::walkable();
+        assert_eq!(map.expand_to(-3, -2, 64), Ok((3, 2)));
+        assert_eq!(map.dimensions(), (5, 4));
+        assert!(map.is_walkable(3, 2));
+
+        assert_eq!(map.expand_to(-1, 0, 64), Ok((1, 0)));
+        assert!(map.is_walkable(4, 2));
+        assert_eq!(map.expand_to(9, 1, 64), Ok((0, 0)));
+        assert_eq!(map.dimensions(), (10, 4));
+    }
+
+    #[test]
+    fn trimming_keeps_fully_blocked_maps() {
+        let mut empty = Map::default();
+        empty.trim();
+        assert!(empty.is_empty());
+
+        let mut blocked = Map::new_blocked(3, 2);
+        blocked.trim();
+        assert_eq!(blocked, Map::new_blocked(3, 2));
+
+        let mut map = Map::from_ascii("#####\n###.#\n##.##\n#####\n").unwrap();
+        map.trim();
+        assert_eq!(map, Map::from_ascii("#.\n.#\n").unwrap());
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let map = Map::from_ascii(".....\n.###.\n.+...\n").unwrap();
+
+        let path = map.find_path((0, 2), (4, 2)).unwrap();
+        assert_eq!(path.first(), Some(&(0, 2)));
+        assert_eq!(path.last(), Some(&(4, 2)));
+        assert_eq!(path.len(), 9);
+        for pair in path.windows(2) {
+            let ((ax, ay), (bx, by)) = (pair[0], pair[1]);
+            assert_eq!(ax.abs_diff(bx) + ay.abs_diff(by), 1);
+            assert!(map.is_walkable(bx, by));
+        }
+
+        assert_eq!(map.find_path((2, 2), (2, 2)), Some(vec![(2, 2)]));
+        assert_eq!(map.find_path((0, 0), (2, 1)), None);
+        assert_eq!(map.find_path((0, 0), (9, 9)), None);
+        assert_eq!(
+            Map::from_ascii(".#.\n").unwrap().find_path((0, 0), (2, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn doors_join_regions_and_walls_split_them() {
+        assert!(Map::new_blocked(3, 3).is_connected());
+
+        let door = Map::from_ascii("#####\n#.D.#\n#####\n").unwrap();
+        assert!(door.is_connected());
+
+        let wall = Map::from_ascii("#####\n#.+.#\n#####\n").unwrap();
+        assert!(!wall.is_connected());
+        assert_eq!(wall.walkable_regions(), vec![vec![(1, 1)], vec![(3, 1)]]);
+    }
+
+    #[test]
+    fn connections_are_checked_against_their_maps() {
+        let mut map = Map::from_ascii("....\n").unwrap();
+        map.tiles[0][0] = Tile::walkable().with_connection("a.map", (0, 0));
+        map.tiles[0][1] = Tile::walkable().with_connection("a.map", (5, 5));
+        map.tiles[0][2] = Tile::walkable().with_connection("a.map", (1, 0));
+        map.tiles[0][3] = Tile::walkable().with_connection("missing.map", (0, 0));
+
+        let resolved = std::cell::RefCell::new(Vec::new());
+        let errors = map.validate_connections(|name| {
+            resolved.borrow_mut().push(name.to_string());
+            (name == "a.map").then(|| Map::from_ascii(".#\n").unwrap())
+        });
+
+        assert_eq!(resolved.into_inner(), ["a.map", "missing.map"]);
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(
+            errors[0],
+            ConnectionError::SpawnOutOfBounds { at: (1, 0), .. }
+        ));
+        assert!(matches!(
+            errors[1],
+            ConnectionError::SpawnBlocked { at: (2, 0), .. }
+        ));
+        assert!(matches!(
+            errors[2],
+            ConnectionError::MissingMap { at: (3, 0), .. }
+        ));
+    }
+
+    fn tile_types(map: &Map) -> Vec<Vec<TileType>> {
+        map.tiles
+            .iter()
+            .filter(|row| !row.is_empty())
+            .map(|row| row.iter().map(|tile| tile.tile_type).collect())
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn run_lengths_keep_tile_types(map in arb_map()) {
+            let decoded = Map::from_rle(&map.to_rle()).unwrap();
+            prop_assert_eq!(tile_types(&decoded), tile_types(&map));
+        }
+    }
+
+    #[test]
+    fn run_lengths_reject_bad_runs() {
+        assert_eq!(
+            Map::from_ascii("##..#\n").unwrap().to_rle(),
+            "2:#,2:.,1:#\n"
+        );
+
+        assert!(matches!(
+            Map::from_rle("2:x"),
+            Err(ParseError::InvalidRun { line: 1, .. })
+        ));
+        assert!(matches!(
+            Map::from_rle("2#"),
+            Err(ParseError::InvalidRun { .. })
+        ));
+        assert!(matches!(
+            Map::from_rle("99999999999:#"),
+            Err(ParseError::InvalidRun { .. })
+        ));
+        assert!(matches!(
+            Map::from_rle("2:#\n3:#"),
+            Err(ParseError::Ragged { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn rotating_turns_tiles_objects_and_spawns() {
+        let mut map = Map::from_ascii("..#\n#D.\n").unwrap();
+        map.tiles[0][0] = Tile::walkable().with_object(ObjectType::Wall, Direction::East.to_quat());
+        map.tiles[0][1] = Tile::walkable().with_connection("", (2, 1));
+        map.tiles[1][2].object = Some(Object::new(
+            ObjectType::Door,
+            Quat::from_xyzw(0.3, 0.4, 0.1, 0.8),
+        ));
+
+        let mut rotated = map.clone();
+        rotated.rotate_90();
+        assert_eq!(rotated.dimensions(), (2, 3));
+        assert_eq!(rotated.to_ascii(), "#+\nD.\nD#\n");
+        assert_eq!(
+            rotated.tiles[1][1],
+            Tile::walkable().with_connection("", (0, 2))
+        );
+        assert_eq!(
+            rotated.tiles[0][1].object,
+            Some(Object::facing(ObjectType::Wall, Direction::South))
+        );
+
+        for _ in 0..3 {
+            rotated.rotate_90();
+        }
+        assert_eq!(rotated.tiles[0], map.tiles[0]);
+        let (turned, original) = (
+            rotated.tiles[1][2].object.unwrap(),
+            map.tiles[1][2].object.unwrap(),
+        );
+        assert!(turned.rotation.dot(original.rotation).abs() > 1.0 - 1e-5);
+
+        let mut empty = Map::default();
+        empty.rotate_90();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn flipping_mirrors_tiles_and_objects() {
+        let mut map = Map::from_ascii("D.#\n#..\n").unwrap();
+        map.tiles[0][0].object = Some(Object::facing(ObjectType::Door, Direction::East));
+        map.tiles[1][1] = Tile::walkable().with_connection("other.map", (4, 4));
+
+        let mut flipped = map.clone();
+        flipped.flip_horizontal();
+        assert_eq!(flipped.to_ascii(), "#.D\n..#\n");
+        assert_eq!(
+            flipped.tiles[0][2].object,
+            Some(Object::facing(ObjectType::Door, Direction::West))
+        );
+        // connections to other maps keep their spawn
+        assert_eq!(flipped.tiles[1][1], map.tiles[1][1]);
+        flipped.flip_horizontal();
+        assert_eq!(flipped, map);
+
+        let mut flipped = map.clone();
+        flipped.flip_vertical();
+        assert_eq!(flipped.to_ascii(), "#..\nD.#\n");
+        assert_eq!(flipped.tiles[1][0].object, map.tiles[0][0].object);
+        flipped.flip_vertical();
+        assert_eq!(flipped, map);
+
+        let mut north = Map::from_ascii("D\n").unwrap();
+        north.tiles[0][0].object = Some(Object::facing(ObjectType::Door, Direction::North));
+        north.flip_vertical();
+        assert_eq!(
+            north.tiles[0][0].object,
+            Some(Object::facing(ObjectType::Door, Direction::South))
+        );
+    }
+
+    #[test]
+    fn rectangles_fill_in_either_corner_order() {
+        let mut map = Map::new_blocked(4, 4);
+        map.fill_rect((1, 1), (2, 2), TileType::Walkable).unwrap();
+        assert_eq!(map.to_ascii(), "####\n#..#\n#..#\n####\n");
+
+        let mut reversed = Map::new_blocked(4, 4);
+        reversed
+            .fill_rect((2, 2), (1, 1), TileType::Walkable)
+            .unwrap();
+        assert_eq!(reversed, map);
+
+        let mut grown = Map::new_blocked(2, 2);
+        grown.fill_rect((1, 0), (3, 2), TileType::Walkable).unwrap();
+        assert_eq!(grown.to_ascii(), "#...\n#...\n#...\n");
+
+        let mut empty = Map::default();
+        empty.fill_rect((0, 0), (0, 0), TileType::Walkable).unwrap();
+        assert_eq!(empty.to_ascii(), ".\n");
+        assert!(empty
+            .fill_rect((0, 0), (usize::MAX, 0), TileType::Walkable)
+            .is_err());
+        assert!(empty
+            .fill_rect((0, 0), (5000, 0), TileType::Walkable)
+            .is_err());
+        assert_eq!(empty.to_ascii(), ".\n");
+    }
+
+    #[test]
+    fn lines_cover_every_tile_between_their_ends() {
+        let line = |from, to| {
+            let mut map = Map::default();
+            map.draw_line(from, to, TileType::Walkable).unwrap();
+            map
+        };
+
+        assert_eq!(
+            line((0, 0), (3, 0)).tiles_of_type(TileType::Walkable),
+            vec![(0, 0), (1, 0), (2, 0), (3, 0)]
+        );
+        assert_eq!(
+            line((1, 3), (1, 0)).tiles_of_type(TileType::Walkable),
+            vec![(1, 0), (1, 1), (1, 2), (1, 3)]
+        );
+        assert_eq!(
+            line((0, 0), (2, 2)).tiles_of_type(TileType::Walkable),
+            vec![(0, 0), (1, 1), (2, 2)]
+        );
+        assert_eq!(
+            line((0, 0), (1, 3)).tiles_of_type(TileType::Walkable),
+            vec![(0, 0), (0, 1), (1, 2), (1, 3)]
+        );
+
+        let backwards = line((3, 1), (0, 0));
+        assert_eq!(backwards.dimensions(), (4, 2));
+        assert_eq!(backwards.tiles_of_type(TileType::Walkable).len(), 4);
+    }
+
+    #[test]
+    fn stamping_overwrites_or_skips_blocked_tiles() {
+        let room = Map::from_ascii("###\n#.#\n###\n").unwrap();
+
+        let mut empty = Map::default();
+        empty.stamp(&room, (2, 1), StampMode::Overwrite).unwrap();
+        assert_eq!(empty.to_ascii(), "#####\n#####\n###.#\n#####\n");
+
+        let open = Map::from_ascii("....\n....\n....\n").unwrap();
+        let mut overwritten = open.clone();
+        overwritten
+            .stamp(&room, (1, 1), StampMode::Overwrite)
+            .unwrap();
+        assert_eq!(overwritten.to_ascii(), "....\n.###\n.#.#\n####\n");
+
+        let mut skipped = open.clone();
+        skipped
+            .stamp(&room, (1, 1), StampMode::SkipBlocked)
+            .unwrap();
+        assert_eq!(skipped.to_ascii(), "....\n....\n....\n####\n");
+
+        let mut portal = Map::from_ascii("..\n").unwrap();
+        portal.tiles[0][0] = Tile::walkable().with_connection("", (1, 0));
+        let mut target = Map::default();
+        target
+            .stamp(&portal, (3, 2), StampMode::SkipBlocked)
+            .unwrap();
+        assert_eq!(
+            target.tiles[2][3],
+            Tile::walkable().with_connection("", (4, 2))
+        );
+
+        assert!(target
+            .stamp(&room, (usize::MAX, 0), StampMode::Overwrite)
+            .is_err());
+    }
+
+    #[test]
+    fn tiles_are_counted_by_type_and_content() {
+        let mut map = Map::from_ascii("#.D\n+.~\n").unwrap();
+        map.tiles[1][1] = Tile::walkable().with_connection("cellar.map", (0, 0));
+
+        let counts = map.tile_counts();
+        assert_eq!(
+            counts,
+            TileCounts {
+                walkable: 4,
+                blocked: 1,
+                water: 1,
+                hazards: 0,
+                doors: 1,
+                walls: 1,
+                connections: 1,
+            }
+        );
+        assert_eq!(
+            counts.to_string(),
+            "4 walkable, 1 blocked, 1 water, 0 hazards, 1 doors, 1 walls, 1 connections"
+        );
+    }
+
+    #[test]
+    fn padding_can_differ_per_side() {
+        let mut map = Map::from_ascii("..\n").unwrap();
+        map.pad_each(2, 0, 1, 0);
+        assert_eq!(map.to_ascii(), "###\n###\n#..\n");
+
+        let mut map = Map::from_ascii(".\n").unwrap();
+        map.pad_each(1, 2, 3, 4);
+        assert_eq!(map.dimensions(), (8, 4));
+        assert_eq!(map.tiles_of_type(TileType::Walkable), vec![(3, 1)]);
+
+        let mut map = Map::from_ascii(".\n").unwrap();
+        map.pad(1);
+        assert_eq!(map.to_ascii(), "###\n#.#\n###\n");
+    }
+
+    #[test]
+    fn cropping_clamps_corners_and_moves_local_spawns() {
+        let map = Map::from_ascii("#.#.\n..##\n#..#\n").unwrap();
+        let cropped = |top_left, bottom_right| {
+            let mut map = map.clone();
+            map.crop(top_left, bottom_right);
+            map
+        };
+
+        assert_eq!(cropped((1, 0), (2, 1)).to_ascii(), ".#\n.#\n");
+        assert_eq!(cropped((2, 1), (100, 100)).to_ascii(), "##\n.#\n");
+        assert_eq!(cropped((3, 2), (3, 2)).to_ascii(), "#\n");
+
+        let mut portal = map.clone();
+        portal.tiles[1][1] = Tile::walkable().with_connection("", (2, 2));
+        portal.crop((1, 1), (2, 2));
+        assert_eq!(
+            portal.tiles[0][0],
+            Tile::walkable().with_connection("", (1, 1))
+        );
+
+        let mut empty = Map::default();
+        empty.crop((0, 0), (5, 5));
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn four_quarter_turns_keep_the_content_hash() {
+        let map = Map::from_ascii("#.D\n+..\n").unwrap();
+        assert_eq!(
+            map.content_hash(),
+            Map::from_ascii("#.D\n+..\n").unwrap().content_hash()
+        );
+
+        let mut turned = map.clone();
+        for _ in 0..4 {
+            turned.rotate_90();
+        }
+        assert_eq!(turned.content_hash(), map.content_hash());
+
+        let mut opened = map.clone();
+        opened.tiles[0][2].object = None;
+        assert_ne!(opened.content_hash(), map.content_hash());
+    }
+
+    #[test]
+    fn world_positions_round_to_the_nearest_tile() {
+        let tile_size = TileSize::square(32.0);
+        assert_eq!(tile_size.world_to_tile(Vec2::new(0.0, 0.0)), (0, 0));
+        assert_eq!(tile_size.world_to_tile(Vec2::new(15.9, -15.9)), (0, 0));
+        assert_eq!(tile_size.world_to_tile(Vec2::new(16.0, -16.1)), (1, -1));
+        assert_eq!(tile_size.world_to_tile(Vec2::new(-48.1, -80.0)), (-2, -2));
+        assert_eq!(tile_size.tile_to_world(-2, 3), Vec2::new(-64.0, 96.0));
+
+        // tiles that aren't square round each axis by its own side
+        let tile_size = TileSize {
+            width: 2.0,
+            height: 1.0,
+        };
+        assert_eq!(tile_size.world_to_tile(Vec2::new(0.9, 0.6)), (0, 1));
+        for x in -5..5 {
+            for y in -5..5 {
+                let world = tile_size.tile_to_world(x, y);
+                assert_eq!(tile_size.world_to_tile(world), (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn extra_objects_stack_as_decorations() {
+        let mut tile = Tile::walkable();
+        tile.add_object(Object::new(ObjectType::Door, Quat::IDENTITY));
+        assert!(!tile.is_wall());
+        tile.add_object(Object::new(ObjectType::Wall, Quat::IDENTITY));
+        assert_eq!(tile.objects().count(), 2);
+        assert_eq!(tile.decorations.len(), 1);
+        assert!(tile.is_wall());
+
+        let mut map = Map {
+            tiles: vec![vec![tile]],
+            spawn: None,
+        };
+        let counts = map.tile_counts();
+        assert_eq!((counts.doors, counts.walls), (1, 1));
+
+        map.rotate_90();
+        map.flip_vertical();
+        assert_eq!(map.tiles[0][0].objects().count(), 2);
+    }
+
+    #[test]
+    fn water_and_hazards_are_open_but_impassable() {
+        for tile_type in TileType::ALL {
+            assert_eq!(tile_type.is_walkable(), tile_type == TileType::Walkable);
+            assert_eq!(TileType::from_symbol(tile_type.symbol()), Some(tile_type));
+        }
+
+        let map = Map::from_ascii(".~^#\n").unwrap();
+        assert!(map.is_walkable(0, 0));
+        assert!(!map.is_walkable(1, 0));
+        assert!(!map.is_walkable(2, 0));
+        assert_eq!(map.find_path((0, 0), (1, 0)), None);
+
+        let counts = map.tile_counts();
+        assert_eq!((counts.water, counts.hazards), (1, 1));
+    }
+
+    #[test]
+    fn directions_turn_forward_onto_their_offset() {
+        let close = |a: Quat, b: Quat| a.dot(b).abs() > 1.0 - 1e-5;
+        assert!(close(Direction::North.to_quat(), Quat::IDENTITY));
+        assert!(close(
+            Direction::East.to_quat(),
+            Quat::from_rotation_y(-FRAC_PI_2)
+        ));
+        assert!(close(Direction::South.to_quat(), Quat::from_rotation_y(PI)));
+        assert!(close(
+            Direction::West.to_quat(),
+            Quat::from_rotation_y(FRAC_PI_2)
+        ));
+
+        for direction in Direction::ALL {
+            let forward = direction.to_quat() * Vec3::NEG_Z;
+            let (dx, dy) = direction.offset();
+            assert!(
+                forward.abs_diff_eq(Vec3::new(dx as f32, 0.0, dy as f32), 1e-4),
+                "{:?}",
+                direction
+            );
+            assert_eq!(Direction::from_quat(direction.to_quat()), Some(direction));
+        }
+    }
+
+    #[test]
+    fn connections_link_both_ways_without_changing_tile_types() {
+        let mut a = Map::from_ascii("..\n.#\n").unwrap();
+        let mut b = Map::from_ascii("#.\n").unwrap();
+
+        link_connection(&mut a, (1, 1), "a.map", &mut b, (0, 0), "b.map");
+        assert_eq!(
+            a.tiles[1][1],
+            Tile::blocked().with_connection("b.map", (0, 0))
+        );
+        assert_eq!(
+            b.tiles[0][0],
+            Tile::blocked().with_connection("a.map", (1, 1))
+        );
+
+        // nothing is linked when either end is outside its map
+        link_connection(&mut a, (5, 5), "a.map", &mut b, (1, 0), "b.map");
+        assert!(b.tiles[0][1].connection.is_none());
+    }
+
+    #[test]
+    fn spawn_falls_back_to_the_first_walkable_tile() {
+        let mut map = Map::from_ascii("##.\n#..\n").unwrap();
+        assert_eq!(map.default_spawn(), Some((2, 0)));
+
+        map.spawn = Some((1, 1));
+        assert_eq!(map.default_spawn(), Some((1, 1)));
+
+        // spawns that can't be stood on or are off the map are ignored
+        map.spawn = Some((0, 0));
+        assert_eq!(map.default_spawn(), Some((2, 0)));
+        map.spawn = Some((7, 7));
+        assert_eq!(map.default_spawn(), Some((2, 0)));
+
+        assert_eq!(Map::from_ascii("##\n").unwrap().default_spawn(), None);
     }
 }